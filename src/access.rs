@@ -0,0 +1,236 @@
+use ::listing;
+
+/// Editor backup and swap files, denied unless asked otherwise
+const DEFAULT_DENY: &'static [&'static str] = &["*~", "*.swp", "*.swo", "#*#"];
+
+/// Which files of the served tree can be reached
+///
+/// A pattern without a slash is matched against the name of every
+/// component of a path, like `*.swp`, and one with a slash against the
+/// path from the root, like `/private/*`. A component matching an allow
+/// pattern is visible even if it is a dotfile or matches a deny pattern,
+/// and so are the directories holding the files an allow pattern with a
+/// slash matches, like `/.well-known` for `/.well-known/*`. Hidden files
+/// are answered with 404 and left out of listings
+#[derive(Clone, Debug)]
+pub struct AccessPolicy {
+    hide_dotfiles: bool,
+    deny: Vec<String>,
+    allow: Vec<String>,
+}
+
+/// Match a glob against the path of a file from the root when it holds a
+/// slash, and against the name of the file otherwise
+pub fn pattern_matches(pattern: &str, path: &str) -> bool {
+    if pattern.contains('/') {
+        listing::glob_matches(pattern, path)
+    } else {
+        listing::glob_matches(pattern, path.rsplit('/').next().unwrap_or(path))
+    }
+}
+
+fn any_matches(patterns: &[String], path: &str) -> bool {
+    patterns.iter().any(|pattern| pattern_matches(pattern, path))
+}
+
+/// Whether a pattern with a slash can match files under the directory
+/// `prefix`, its leading components matching those of the directory
+///
+/// The name of the directory itself has to be spelled out, so that `/*/x`
+/// does not uncover every hidden file at the top
+fn leads_into(pattern: &str, prefix: &str) -> bool {
+    if !pattern.contains('/') {
+        return false;
+    }
+
+    let depth = prefix.matches('/').count();
+    let components: Vec<&str> = pattern.split('/').collect();
+    if components.len() <= depth + 1 || components[depth].contains('*') || components[depth].contains('?') {
+        return false;
+    }
+
+    listing::glob_matches(&components[..depth + 1].join("/"), prefix)
+}
+
+impl AccessPolicy {
+    /// Hide dotfiles and editor backup files
+    pub fn new() -> AccessPolicy {
+        AccessPolicy {
+            hide_dotfiles: true,
+            deny: DEFAULT_DENY.iter().map(|p| p.to_string()).collect(),
+            allow: Vec::new(),
+        }
+    }
+
+    /// Policy under which every file is visible
+    pub fn allow_all() -> AccessPolicy {
+        AccessPolicy {
+            hide_dotfiles: false,
+            deny: Vec::new(),
+            allow: Vec::new(),
+        }
+    }
+
+    pub fn with_hidden_dotfiles(&mut self, hide_dotfiles: bool) -> &mut Self {
+        self.hide_dotfiles = hide_dotfiles;
+        self
+    }
+
+    pub fn with_deny(&mut self, pattern: &str) -> &mut Self {
+        self.deny.push(pattern.to_owned());
+        self
+    }
+
+    pub fn with_allow(&mut self, pattern: &str) -> &mut Self {
+        self.allow.push(pattern.to_owned());
+        self
+    }
+
+    /// Whether the file at `path`, from the root and starting with a
+    /// slash, can be served or listed
+    pub fn is_visible(&self, path: &str) -> bool {
+        if any_matches(&self.allow, path) {
+            return true;
+        }
+
+        let names: Vec<&str> = path.split('/').filter(|c| !c.is_empty()).collect();
+        let mut prefix = String::new();
+
+        for (i, name) in names.iter().enumerate() {
+            prefix.push('/');
+            prefix.push_str(name);
+
+            if any_matches(&self.allow, &prefix) {
+                continue;
+            }
+
+            // a directory holding allowed files can be reached, but not the
+            // rest of what it holds
+            if i == names.len() - 1 && self.allow.iter().any(|pattern| leads_into(pattern, &prefix)) {
+                continue;
+            }
+
+            if (self.hide_dotfiles && name.starts_with('.')) || any_matches(&self.deny, &prefix) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_policy() {
+        let access = AccessPolicy::new();
+
+        assert!(access.is_visible("/"));
+        assert!(access.is_visible("/index.html"));
+        assert!(access.is_visible("/docs/guide.md"));
+        assert!(access.is_visible("/docs/a.b.c"));
+
+        for path in &["/.env", "/.git", "/.git/config", "/docs/.hidden", "/notes.txt~", "/src/.main.rs.swp",
+                      "/x.swo", "/#draft#"] {
+            assert!(!access.is_visible(path), "{}", path);
+        }
+    }
+
+    #[test]
+    fn nested_hidden_directories() {
+        let access = AccessPolicy::new();
+
+        assert!(!access.is_visible("/a/.cache/b/c.txt"));
+        assert!(!access.is_visible("/.config/app/settings.json"));
+        assert!(!access.is_visible("/a/backup~/file"));
+    }
+
+    #[test]
+    fn allow_all() {
+        let access = AccessPolicy::allow_all();
+
+        assert!(access.is_visible("/.env"));
+        assert!(access.is_visible("/x.swp"));
+    }
+
+    #[test]
+    fn deny_globs() {
+        let mut access = AccessPolicy::new();
+        access.with_deny("*.bak").with_deny("/private/*").with_deny("secret?");
+
+        assert!(!access.is_visible("/db.bak"));
+        assert!(!access.is_visible("/nested/dir/db.bak"));
+        assert!(!access.is_visible("/private/keys"));
+        assert!(!access.is_visible("/private/deep/keys"));
+        assert!(!access.is_visible("/dir/secret1"));
+        assert!(!access.is_visible("/secret1/inside"));
+
+        // path patterns are anchored at the root
+        assert!(access.is_visible("/public/private/keys"));
+        assert!(access.is_visible("/private"));
+        assert!(access.is_visible("/secret"));
+        assert!(access.is_visible("/db.bak.txt"));
+    }
+
+    #[test]
+    fn dotfiles_can_be_shown() {
+        let mut access = AccessPolicy::new();
+        access.with_hidden_dotfiles(false);
+
+        assert!(access.is_visible("/.env"));
+        assert!(access.is_visible("/.git/config"));
+        assert!(!access.is_visible("/notes.txt~"));
+    }
+
+    #[test]
+    fn allow_names() {
+        let mut access = AccessPolicy::new();
+        access.with_allow(".well-known").with_allow(".htaccess");
+
+        assert!(access.is_visible("/.well-known"));
+        assert!(access.is_visible("/.well-known/security.txt"));
+        assert!(access.is_visible("/site/.htaccess"));
+
+        // other dotfiles stay hidden, including inside an allowed directory
+        assert!(!access.is_visible("/.env"));
+        assert!(!access.is_visible("/.well-known/.secret"));
+    }
+
+    #[test]
+    fn allow_paths() {
+        let mut access = AccessPolicy::new();
+        access.with_allow("/.git/*").with_allow("/*/.config/app.json");
+
+        assert!(access.is_visible("/.git"));
+        assert!(access.is_visible("/.git/config"));
+        assert!(access.is_visible("/.git/objects/ab/cdef"));
+        assert!(access.is_visible("/home/.config"));
+        assert!(access.is_visible("/home/.config/app.json"));
+
+        assert!(!access.is_visible("/home/.config/other.json"));
+        assert!(!access.is_visible("/home/.config/app.json/inside"));
+        assert!(!access.is_visible("/sub/.git/config"));
+        assert!(!access.is_visible("/.gitignore"));
+    }
+
+    #[test]
+    fn allow_overrides_deny() {
+        let mut access = AccessPolicy::new();
+        access.with_deny("/private/*").with_allow("/private/public.txt").with_allow("keep.swp");
+
+        assert!(access.is_visible("/private/public.txt"));
+        assert!(!access.is_visible("/private/other.txt"));
+        assert!(access.is_visible("/keep.swp"));
+        assert!(!access.is_visible("/other.swp"));
+    }
+
+    #[test]
+    fn patterns() {
+        assert!(pattern_matches("*.txt", "/a/b/c.txt"));
+        assert!(!pattern_matches("*.txt", "/a.txt/b"));
+        assert!(pattern_matches("/a/*", "/a/b/c"));
+        assert!(!pattern_matches("/a/*", "/b/a/c"));
+    }
+}
@@ -0,0 +1,719 @@
+use std::fs::{self, File, Metadata};
+use std::io::{self, Read, Write, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use flate2::{Compression, Crc};
+use flate2::write::{DeflateEncoder, GzEncoder};
+use time::{self, Timespec};
+
+use ::conditional;
+
+const BLOCK_SIZE: usize = 512;
+
+/// Largest size the octal field of a tar header holds, larger files get
+/// their size from a PAX extended header
+const MAX_TAR_SIZE: u64 = 0o77777777777;
+
+/// Format of a directory archive
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    Tar,
+    TarGz,
+    Zip,
+}
+
+impl Kind {
+    pub fn all() -> [Kind; 3] {
+        [Kind::Tar, Kind::TarGz, Kind::Zip]
+    }
+
+    /// Kind named by the `archive` query parameter
+    pub fn from_name(name: &str) -> Option<Kind> {
+        match name {
+            "tar" => Some(Kind::Tar),
+            "tar.gz" | "tgz" => Some(Kind::TarGz),
+            "zip" => Some(Kind::Zip),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            Kind::Tar => "tar",
+            Kind::TarGz => "tar.gz",
+            Kind::Zip => "zip",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match *self {
+            Kind::Tar => "application/x-tar",
+            Kind::TarGz => "application/gzip",
+            Kind::Zip => "application/zip",
+        }
+    }
+}
+
+/// Something an archive is built with, receiving the entries of the tree
+/// as it is walked
+trait Builder {
+    fn directory(&mut self, name: &str, metadata: &Metadata) -> Result<(), io::Error>;
+    fn file(&mut self, name: &str, file: &mut File, metadata: &Metadata) -> Result<(), io::Error>;
+}
+
+/// Copy exactly `len` bytes of `file`, padding with zeros if it shrank
+/// since its size was taken, as the size is already written in the archive
+fn copy_exact<W: Write, F: FnMut(&[u8])>(file: &mut File, len: u64, out: &mut W, mut inspect: F) -> Result<(), io::Error> {
+    let mut buf = [0u8; 16 * 1024];
+    let mut left = len;
+
+    while left > 0 {
+        let want = if left < buf.len() as u64 { left as usize } else { buf.len() };
+        let read = match file.read(&mut buf[..want]) {
+            Ok(0) => {
+                for b in buf[..want].iter_mut() {
+                    *b = 0;
+                }
+                want
+            },
+            Ok(read) => read,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+
+        inspect(&buf[..read]);
+        try!(out.write_all(&buf[..read]));
+        left -= read as u64;
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn mode(metadata: &Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn mode(metadata: &Metadata) -> u32 {
+    if metadata.is_dir() { 0o755 } else { 0o644 }
+}
+
+struct TarBuilder<W: Write> {
+    out: W,
+}
+
+/// Write `value` as a NUL-terminated octal number filling `field`
+fn octal(field: &mut [u8], value: u64) {
+    let width = field.len() - 1;
+    let digits = format!("{:01$o}", value, width);
+    let start = digits.len() - width;
+    field[..width].copy_from_slice(&digits.as_bytes()[start..]);
+    field[width] = 0;
+}
+
+/// One `length key=value` record of a PAX extended header, the length
+/// counting its own digits
+fn pax_record(key: &str, value: &str) -> String {
+    let rest = key.len() + value.len() + 3;
+    let mut length = rest + 1;
+    while length.to_string().len() + rest > length {
+        length += 1;
+    }
+    format!("{} {}={}\n", length, key, value)
+}
+
+impl<W: Write> TarBuilder<W> {
+    fn header(&mut self, name: &str, size: u64, mode: u32, modified: i64, kind: u8) -> Result<(), io::Error> {
+        let mut header = [0u8; BLOCK_SIZE];
+
+        let name_bytes = name.as_bytes();
+        let length = if name_bytes.len() < 100 { name_bytes.len() } else { 100 };
+        header[..length].copy_from_slice(&name_bytes[..length]);
+
+        octal(&mut header[100..108], mode as u64);
+        octal(&mut header[108..116], 0);
+        octal(&mut header[116..124], 0);
+        octal(&mut header[124..136], if size > MAX_TAR_SIZE { 0 } else { size });
+        octal(&mut header[136..148], if modified > 0 { modified as u64 } else { 0 });
+        header[156] = kind;
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+
+        // the checksum is computed with its own field filled with spaces
+        for b in header[148..156].iter_mut() {
+            *b = b' ';
+        }
+        let checksum = header.iter().fold(0u64, |sum, &b| sum + b as u64);
+        octal(&mut header[148..155], checksum);
+        header[155] = b' ';
+
+        self.out.write_all(&header)
+    }
+
+    fn pad(&mut self, size: u64) -> Result<(), io::Error> {
+        let rest = (size % BLOCK_SIZE as u64) as usize;
+        if rest > 0 {
+            try!(self.out.write_all(&[0u8; BLOCK_SIZE][..BLOCK_SIZE - rest]));
+        }
+        Ok(())
+    }
+
+    /// Write an entry header, preceded by a PAX extended header when the
+    /// name or size do not fit the ustar fields
+    fn entry(&mut self, name: &str, size: u64, metadata: &Metadata, kind: u8) -> Result<(), io::Error> {
+        let modified = conditional::modified_time(metadata).unwrap_or(0);
+
+        let mut pax = String::new();
+        if name.len() >= 100 || !name.is_ascii() {
+            pax.push_str(&pax_record("path", name));
+        }
+        if size > MAX_TAR_SIZE {
+            pax.push_str(&pax_record("size", &size.to_string()));
+        }
+
+        if !pax.is_empty() {
+            try!(self.header("././@PaxHeader", pax.len() as u64, 0o644, modified, b'x'));
+            try!(self.out.write_all(pax.as_bytes()));
+            try!(self.pad(pax.len() as u64));
+        }
+
+        self.header(name, size, mode(metadata), modified, kind)
+    }
+
+    /// Write the two empty blocks ending the archive
+    fn finish(mut self) -> Result<W, io::Error> {
+        try!(self.out.write_all(&[0u8; BLOCK_SIZE * 2]));
+        Ok(self.out)
+    }
+}
+
+impl<W: Write> Builder for TarBuilder<W> {
+    fn directory(&mut self, name: &str, metadata: &Metadata) -> Result<(), io::Error> {
+        self.entry(&format!("{}/", name), 0, metadata, b'5')
+    }
+
+    fn file(&mut self, name: &str, file: &mut File, metadata: &Metadata) -> Result<(), io::Error> {
+        let size = metadata.len();
+        try!(self.entry(name, size, metadata, b'0'));
+        try!(copy_exact(file, size, &mut self.out, |_| {}));
+        self.pad(size)
+    }
+}
+
+/// Writer keeping count of the bytes written, for the offsets zip records
+struct Counter<W: Write> {
+    out: W,
+    count: u64,
+}
+
+impl<W: Write> Write for Counter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
+        let written = try!(self.out.write(buf));
+        self.count += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        self.out.flush()
+    }
+}
+
+fn push_u16(buf: &mut Vec<u8>, value: u16) {
+    buf.push(value as u8);
+    buf.push((value >> 8) as u8);
+}
+
+fn push_u32(buf: &mut Vec<u8>, value: u32) {
+    push_u16(buf, value as u16);
+    push_u16(buf, (value >> 16) as u16);
+}
+
+/// Modification time and date of a file in the MS-DOS format zip uses
+fn dos_time(metadata: &Metadata) -> (u16, u16) {
+    let tm = time::at_utc(Timespec::new(conditional::modified_time(metadata).unwrap_or(0), 0));
+    if tm.tm_year < 80 {
+        // the earliest time the format can hold, 1980-01-01
+        return (0, (1 << 5) | 1);
+    }
+
+    let time = (tm.tm_hour << 11) | (tm.tm_min << 5) | (tm.tm_sec / 2);
+    let date = ((tm.tm_year - 80) << 9) | ((tm.tm_mon + 1) << 5) | tm.tm_mday;
+    (time as u16, date as u16)
+}
+
+/// Writer of a zip archive as a stream, the sizes and checksum of each file
+/// following its data in a data descriptor
+///
+/// Without ZIP64 the archive and each file in it are limited to 4 GiB, the
+/// tar formats having no such limit
+struct ZipBuilder<W: Write> {
+    out: Counter<W>,
+    central_directory: Vec<u8>,
+    entries: u64,
+}
+
+/// General purpose flags: sizes in a data descriptor, UTF-8 names
+const ZIP_FLAGS: u16 = 0x0808;
+
+fn zip_too_large() -> io::Error {
+    io::Error::new(ErrorKind::Other, "Directory too large for a zip archive")
+}
+
+impl<W: Write> ZipBuilder<W> {
+    fn new(out: W) -> ZipBuilder<W> {
+        ZipBuilder {
+            out: Counter { out: out, count: 0 },
+            central_directory: Vec::new(),
+            entries: 0,
+        }
+    }
+
+    fn add(&mut self, name: &str, metadata: &Metadata, data: Option<&mut File>) -> Result<(), io::Error> {
+        let offset = self.out.count;
+        if offset > u32::max_value() as u64 || self.entries >= u16::max_value() as u64 {
+            return Err(zip_too_large());
+        }
+
+        let (time, date) = dos_time(metadata);
+        let method = if data.is_some() { 8 } else { 0 };
+
+        let mut local = Vec::new();
+        push_u32(&mut local, 0x04034b50);
+        push_u16(&mut local, 20);
+        push_u16(&mut local, ZIP_FLAGS);
+        push_u16(&mut local, method);
+        push_u16(&mut local, time);
+        push_u16(&mut local, date);
+        // checksum and sizes come in the data descriptor
+        push_u32(&mut local, 0);
+        push_u32(&mut local, 0);
+        push_u32(&mut local, 0);
+        push_u16(&mut local, name.len() as u16);
+        push_u16(&mut local, 0);
+        local.extend_from_slice(name.as_bytes());
+        try!(self.out.write_all(&local));
+
+        let mut crc = Crc::new();
+        let size = metadata.len();
+        let start = self.out.count;
+
+        if let Some(file) = data {
+            if size > u32::max_value() as u64 {
+                return Err(zip_too_large());
+            }
+
+            let mut encoder = DeflateEncoder::new(&mut self.out, Compression::Default);
+            try!(copy_exact(file, size, &mut encoder, |buf| crc.update(buf)));
+            try!(encoder.finish());
+        }
+
+        let (size, compressed) = if method == 0 { (0, 0) } else { (size, self.out.count - start) };
+        if compressed > u32::max_value() as u64 {
+            return Err(zip_too_large());
+        }
+
+        let mut descriptor = Vec::new();
+        push_u32(&mut descriptor, 0x08074b50);
+        push_u32(&mut descriptor, crc.sum());
+        push_u32(&mut descriptor, compressed as u32);
+        push_u32(&mut descriptor, size as u32);
+        try!(self.out.write_all(&descriptor));
+
+        let external = (mode(metadata) | if method == 0 { 0o040000 } else { 0o100000 }) << 16;
+
+        let central = &mut self.central_directory;
+        push_u32(central, 0x02014b50);
+        // made by a Unix system, for version 2.0 of the format
+        push_u16(central, (3 << 8) | 20);
+        push_u16(central, 20);
+        push_u16(central, ZIP_FLAGS);
+        push_u16(central, method);
+        push_u16(central, time);
+        push_u16(central, date);
+        push_u32(central, crc.sum());
+        push_u32(central, compressed as u32);
+        push_u32(central, size as u32);
+        push_u16(central, name.len() as u16);
+        push_u16(central, 0);
+        push_u16(central, 0);
+        push_u16(central, 0);
+        push_u16(central, 0);
+        push_u32(central, external);
+        push_u32(central, offset as u32);
+        central.extend_from_slice(name.as_bytes());
+
+        self.entries += 1;
+        Ok(())
+    }
+
+    /// Write the central directory and its end record
+    fn finish(mut self) -> Result<W, io::Error> {
+        let offset = self.out.count;
+        let size = self.central_directory.len() as u64;
+        if offset + size > u32::max_value() as u64 {
+            return Err(zip_too_large());
+        }
+
+        try!(self.out.write_all(&self.central_directory));
+
+        let mut end = Vec::new();
+        push_u32(&mut end, 0x06054b50);
+        push_u16(&mut end, 0);
+        push_u16(&mut end, 0);
+        push_u16(&mut end, self.entries as u16);
+        push_u16(&mut end, self.entries as u16);
+        push_u32(&mut end, size as u32);
+        push_u32(&mut end, offset as u32);
+        push_u16(&mut end, 0);
+        try!(self.out.write_all(&end));
+
+        Ok(self.out.out)
+    }
+}
+
+impl<W: Write> Builder for ZipBuilder<W> {
+    fn directory(&mut self, name: &str, metadata: &Metadata) -> Result<(), io::Error> {
+        self.add(&format!("{}/", name), metadata, None)
+    }
+
+    fn file(&mut self, name: &str, file: &mut File, metadata: &Metadata) -> Result<(), io::Error> {
+        self.add(name, metadata, Some(file))
+    }
+}
+
+/// Add what `dir` holds to the archive, under `prefix`
+///
+/// Entries `include` refuses are left out, along with those that cannot be
+/// read, which are only found once the archive is being sent
+fn walk<B: Builder, F: Fn(&Path) -> bool>(builder: &mut B, dir: &Path, prefix: &str,
+                                          include: &F, ancestors: &mut Vec<PathBuf>) -> Result<(), io::Error> {
+    // a directory reached again through a symbolic link would never end
+    let canonical = match fs::canonicalize(dir) {
+        Ok(canonical) => canonical,
+        Err(_) => return Ok(()),
+    };
+    if ancestors.contains(&canonical) {
+        return Ok(());
+    }
+
+    let mut names: Vec<_> = match fs::read_dir(dir) {
+        Ok(entries) => entries.filter_map(|entry| entry.ok()).map(|entry| entry.file_name()).collect(),
+        Err(_) => return Ok(()),
+    };
+    names.sort();
+
+    ancestors.push(canonical);
+
+    for file_name in names {
+        let path = dir.join(&file_name);
+        if !include(&path) {
+            continue;
+        }
+
+        let metadata = match fs::metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+        let name = format!("{}/{}", prefix, file_name.to_string_lossy());
+
+        if metadata.is_dir() {
+            try!(builder.directory(&name, &metadata));
+            try!(walk(builder, &path, &name, include, ancestors));
+        } else if metadata.is_file() {
+            let mut file = match File::open(&path) {
+                Ok(file) => file,
+                Err(_) => continue,
+            };
+            try!(builder.file(&name, &mut file, &metadata));
+        }
+    }
+
+    ancestors.pop();
+    Ok(())
+}
+
+/// Stream an archive of the tree under `dir` to `out`, its entries being
+/// named from `name`, and leaving out the paths `include` refuses
+pub fn write<W: Write, F: Fn(&Path) -> bool>(out: W, kind: Kind, dir: &Path, name: &str, include: F) -> Result<(), io::Error> {
+    let mut ancestors = Vec::new();
+
+    match kind {
+        Kind::Tar => {
+            let mut tar = TarBuilder { out: out };
+            try!(walk(&mut tar, dir, name, &include, &mut ancestors));
+            try!(tar.finish());
+        },
+        Kind::TarGz => {
+            let mut tar = TarBuilder { out: GzEncoder::new(out, Compression::Default) };
+            try!(walk(&mut tar, dir, name, &include, &mut ancestors));
+            try!(try!(tar.finish()).finish());
+        },
+        Kind::Zip => {
+            let mut zip = ZipBuilder::new(out);
+            try!(walk(&mut zip, dir, name, &include, &mut ancestors));
+            try!(zip.finish());
+        },
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    use flate2::read::{DeflateDecoder, GzDecoder};
+
+    /// Entry read back from an archive: name, whether it is a directory, content
+    type ReadEntry = (String, bool, Vec<u8>);
+
+    /// Directory of test files, removed when dropped
+    struct Tree {
+        root: PathBuf,
+    }
+
+    impl Tree {
+        fn new(name: &str) -> Tree {
+            let root = env::temp_dir().join(format!("mahardhika-archive-{}-{}", name, process::id()));
+            let _ = fs::remove_dir_all(&root);
+
+            for dir in &["docs", "docs/deep", "empty", ".git"] {
+                fs::create_dir_all(root.join(dir)).unwrap();
+            }
+            let files: &[(&str, &[u8])] = &[
+                ("a.txt", b"hello"),
+                ("docs/guide.md", b"# Guide\n"),
+                ("docs/deep/data.bin", &[0u8, 1, 2, 255]),
+                (".git/HEAD", b"ref"),
+                ("large", &[b'x'; 3000]),
+            ];
+            for &(path, content) in files {
+                File::create(root.join(path)).unwrap().write_all(content).unwrap();
+            }
+
+            Tree { root: root }
+        }
+
+        fn archive(&self, kind: Kind) -> Vec<u8> {
+            let mut out = Vec::new();
+            let root = self.root.clone();
+            write(&mut out, kind, &self.root, "site", |path| {
+                !path.strip_prefix(&root).unwrap().to_string_lossy().starts_with('.')
+            }).unwrap();
+            out
+        }
+    }
+
+    impl Drop for Tree {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    fn expected() -> Vec<ReadEntry> {
+        vec![
+            ("site/a.txt".to_owned(), false, b"hello".to_vec()),
+            ("site/docs/".to_owned(), true, Vec::new()),
+            ("site/docs/deep/".to_owned(), true, Vec::new()),
+            ("site/docs/deep/data.bin".to_owned(), false, vec![0, 1, 2, 255]),
+            ("site/docs/guide.md".to_owned(), false, b"# Guide\n".to_vec()),
+            ("site/empty/".to_owned(), true, Vec::new()),
+            ("site/large".to_owned(), false, vec![b'x'; 3000]),
+        ]
+    }
+
+    fn read_octal(field: &[u8]) -> u64 {
+        let digits: String = field.iter().take_while(|&&b| b != 0 && b != b' ').map(|&b| b as char).collect();
+        u64::from_str_radix(&digits, 8).unwrap()
+    }
+
+    fn read_tar(data: &[u8]) -> Vec<ReadEntry> {
+        assert_eq!(data.len() % BLOCK_SIZE, 0);
+        assert!(data[data.len() - 2 * BLOCK_SIZE..].iter().all(|&b| b == 0));
+
+        let mut entries = Vec::new();
+        let mut pax_path = None;
+        let mut pos = 0;
+
+        while data[pos..pos + BLOCK_SIZE].iter().any(|&b| b != 0) {
+            let header = &data[pos..pos + BLOCK_SIZE];
+            assert_eq!(&header[257..263], b"ustar\0");
+
+            let mut unsigned = header.to_vec();
+            for b in unsigned[148..156].iter_mut() {
+                *b = b' ';
+            }
+            assert_eq!(read_octal(&header[148..156]), unsigned.iter().fold(0, |sum, &b| sum + b as u64));
+
+            let name_length = header[..100].iter().position(|&b| b == 0).unwrap_or(100);
+            let name = String::from_utf8(header[..name_length].to_vec()).unwrap();
+            let size = read_octal(&header[124..136]) as usize;
+            let content = data[pos + BLOCK_SIZE..pos + BLOCK_SIZE + size].to_vec();
+            pos += BLOCK_SIZE + (size + BLOCK_SIZE - 1) / BLOCK_SIZE * BLOCK_SIZE;
+
+            match header[156] {
+                b'x' => {
+                    let records = String::from_utf8(content).unwrap();
+                    for record in records.lines() {
+                        let (length, rest) = record.split_at(record.find(' ').unwrap());
+                        assert_eq!(length.parse::<usize>().unwrap(), record.len() + 1);
+                        if rest.starts_with(" path=") {
+                            pax_path = Some(rest[6..].to_owned());
+                        }
+                    }
+                },
+                kind => {
+                    assert!(kind == b'0' || kind == b'5');
+                    let name = pax_path.take().unwrap_or(name);
+                    entries.push((name, kind == b'5', content));
+                },
+            }
+        }
+
+        entries
+    }
+
+    fn u16_at(data: &[u8], pos: usize) -> usize {
+        data[pos] as usize | (data[pos + 1] as usize) << 8
+    }
+
+    fn u32_at(data: &[u8], pos: usize) -> usize {
+        u16_at(data, pos) | u16_at(data, pos + 2) << 16
+    }
+
+    fn read_zip(data: &[u8]) -> Vec<ReadEntry> {
+        let end = data.len() - 22;
+        assert_eq!(u32_at(data, end), 0x06054b50);
+        let count = u16_at(data, end + 10);
+        let mut pos = u32_at(data, end + 16);
+        assert_eq!(pos + u32_at(data, end + 12), end);
+
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            assert_eq!(u32_at(data, pos), 0x02014b50);
+            let method = u16_at(data, pos + 10);
+            let crc = u32_at(data, pos + 16) as u32;
+            let compressed = u32_at(data, pos + 20);
+            let size = u32_at(data, pos + 24);
+            let name_length = u16_at(data, pos + 28);
+            let offset = u32_at(data, pos + 42);
+            let name = String::from_utf8(data[pos + 46..pos + 46 + name_length].to_vec()).unwrap();
+            pos += 46 + name_length;
+
+            // the local header carries the same name, the data following it
+            assert_eq!(u32_at(data, offset), 0x04034b50);
+            assert_eq!(u16_at(data, offset + 26), name_length);
+            let start = offset + 30 + name_length + u16_at(data, offset + 28);
+            assert_eq!(&data[offset + 30..offset + 30 + name_length], name.as_bytes());
+
+            let mut content = Vec::new();
+            if method == 8 {
+                DeflateDecoder::new(&data[start..start + compressed]).read_to_end(&mut content).unwrap();
+            } else {
+                assert_eq!(method, 0);
+                content.extend_from_slice(&data[start..start + compressed]);
+            }
+            assert_eq!(content.len(), size);
+
+            let mut check = Crc::new();
+            check.update(&content);
+            assert_eq!(check.sum(), crc);
+
+            // followed by the data descriptor repeating the checksum and sizes
+            let descriptor = start + compressed;
+            assert_eq!(u32_at(data, descriptor), 0x08074b50);
+            assert_eq!(u32_at(data, descriptor + 4) as u32, crc);
+            assert_eq!(u32_at(data, descriptor + 8), compressed);
+            assert_eq!(u32_at(data, descriptor + 12), size);
+
+            entries.push((name.clone(), name.ends_with('/'), content));
+        }
+
+        entries
+    }
+
+    #[test]
+    fn kind_names() {
+        for kind in &Kind::all() {
+            assert_eq!(Kind::from_name(kind.extension()), Some(*kind));
+        }
+        assert_eq!(Kind::from_name("tgz"), Some(Kind::TarGz));
+        assert_eq!(Kind::from_name("rar"), None);
+    }
+
+    #[test]
+    fn octal_fields() {
+        let mut field = [0xffu8; 8];
+        octal(&mut field, 0o644);
+        assert_eq!(&field, b"0000644\0");
+
+        // only the lowest digits are kept when the value does not fit
+        octal(&mut field, 0o123456701);
+        assert_eq!(&field, b"3456701\0");
+    }
+
+    #[test]
+    fn pax_record_lengths() {
+        assert_eq!(pax_record("path", "a"), "9 path=a\n");
+
+        // the length gaining a digit makes the record one byte longer
+        for length in 1..200 {
+            let value: String = ::std::iter::repeat('v').take(length).collect();
+            let record = pax_record("path", &value);
+            let (digits, _) = record.split_at(record.find(' ').unwrap());
+            assert_eq!(digits.parse::<usize>().unwrap(), record.len());
+        }
+    }
+
+    #[test]
+    fn tar() {
+        let tree = Tree::new("tar");
+        assert_eq!(read_tar(&tree.archive(Kind::Tar)), expected());
+    }
+
+    #[test]
+    fn tar_gz() {
+        let tree = Tree::new("tar-gz");
+        let archive = tree.archive(Kind::TarGz);
+
+        let mut tar = Vec::new();
+        GzDecoder::new(&archive[..]).unwrap().read_to_end(&mut tar).unwrap();
+        assert_eq!(read_tar(&tar), expected());
+    }
+
+    #[test]
+    fn zip() {
+        let tree = Tree::new("zip");
+        assert_eq!(read_zip(&tree.archive(Kind::Zip)), expected());
+    }
+
+    #[test]
+    fn long_and_unicode_names() {
+        let tree = Tree::new("names");
+        let long: String = ::std::iter::repeat('n').take(150).collect();
+        File::create(tree.root.join(&long)).unwrap().write_all(b"long").unwrap();
+        File::create(tree.root.join("été.txt")).unwrap().write_all(b"summer").unwrap();
+
+        for entries in vec![read_tar(&tree.archive(Kind::Tar)), read_zip(&tree.archive(Kind::Zip))] {
+            assert!(entries.contains(&(format!("site/{}", long), false, b"long".to_vec())));
+            assert!(entries.contains(&("site/été.txt".to_owned(), false, b"summer".to_vec())));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symbolic_link_loops_end() {
+        use std::os::unix::fs::symlink;
+
+        let tree = Tree::new("loop");
+        symlink(&tree.root, tree.root.join("docs/up")).unwrap();
+
+        let entries = read_tar(&tree.archive(Kind::Tar));
+        let names: Vec<&str> = entries.iter().map(|e| &e.0[..]).filter(|n| n.starts_with("site/docs/up")).collect();
+        assert_eq!(names, vec!["site/docs/up/"]);
+    }
+}
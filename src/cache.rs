@@ -0,0 +1,168 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use ::conditional;
+
+/// A file held in memory with what is needed to answer for it
+#[derive(Debug)]
+pub struct CachedFile {
+    pub body: Vec<u8>,
+    pub mime: String,
+    /// Entity tag of the file when it was read, which changes with its
+    /// inode, size or modification time
+    pub etag: String,
+    pub modified: Option<i64>,
+}
+
+/// Counters of a `FileCache`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CacheStats {
+    pub hits: usize,
+    pub misses: usize,
+    pub entries: usize,
+    pub bytes: u64,
+}
+
+/// Files are cached under the path asked for and the content coding of
+/// the file that was sent for it
+type Key = (PathBuf, Option<String>);
+
+#[derive(Debug)]
+struct Entry {
+    file: Arc<CachedFile>,
+    last_used: u64,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    entries: HashMap<Key, Entry>,
+    /// Keys by the time they were last used, the oldest first
+    order: BTreeMap<u64, Key>,
+    bytes: u64,
+    clock: u64,
+}
+
+impl State {
+    fn remove(&mut self, key: &Key) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.order.remove(&entry.last_used);
+            self.bytes -= entry.file.body.len() as u64;
+        }
+    }
+
+    fn evict_oldest(&mut self) -> bool {
+        let oldest = match self.order.keys().next() {
+            Some(&oldest) => oldest,
+            None => return false,
+        };
+
+        if let Some(key) = self.order.remove(&oldest) {
+            self.remove(&key);
+        }
+        true
+    }
+}
+
+/// Least recently used cache of small files, bounded by the bytes it holds
+///
+/// Entries are checked against the metadata of the file on every lookup,
+/// so a file that was replaced or modified is read again. The cache can be
+/// shared with the code that set the server up to read its counters
+#[derive(Debug)]
+pub struct FileCache {
+    state: Mutex<State>,
+    max_bytes: u64,
+    max_entry_size: u64,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+impl FileCache {
+    pub fn new(max_bytes: u64, max_entry_size: u64) -> FileCache {
+        FileCache {
+            state: Mutex::new(State::default()),
+            max_bytes: max_bytes,
+            max_entry_size: max_entry_size,
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+        }
+    }
+
+    /// Whether a file is small enough to be cached
+    pub fn accepts(&self, metadata: &Metadata) -> bool {
+        metadata.len() <= self.max_entry_size && metadata.len() <= self.max_bytes
+    }
+
+    /// Look a file up, `metadata` being the current one of the file it was read from
+    pub fn get(&self, path: &Path, coding: Option<&str>, metadata: &Metadata) -> Option<Arc<CachedFile>> {
+        let key = (path.to_path_buf(), coding.map(|c| c.to_owned()));
+        let mut state = self.state.lock().unwrap();
+
+        let current = match state.entries.get(&key) {
+            Some(entry) => entry.file.etag == conditional::etag(metadata),
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                return None;
+            },
+        };
+
+        if !current {
+            state.remove(&key);
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+
+        state.clock += 1;
+        let now = state.clock;
+
+        let (file, last_used) = {
+            let entry = state.entries.get_mut(&key).unwrap();
+            let last_used = entry.last_used;
+            entry.last_used = now;
+            (entry.file.clone(), last_used)
+        };
+        state.order.remove(&last_used);
+        state.order.insert(now, key);
+
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Some(file)
+    }
+
+    /// Add a file, evicting the least recently used ones to make room
+    pub fn insert(&self, path: &Path, coding: Option<&str>, file: Arc<CachedFile>) {
+        let size = file.body.len() as u64;
+        if size > self.max_entry_size || size > self.max_bytes {
+            return;
+        }
+
+        let key = (path.to_path_buf(), coding.map(|c| c.to_owned()));
+        let mut state = self.state.lock().unwrap();
+        state.remove(&key);
+
+        while state.bytes + size > self.max_bytes {
+            if !state.evict_oldest() {
+                break;
+            }
+        }
+
+        state.clock += 1;
+        let now = state.clock;
+        state.bytes += size;
+        state.order.insert(now, key.clone());
+        state.entries.insert(key, Entry { file: file, last_used: now });
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let state = self.state.lock().unwrap();
+
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: state.entries.len(),
+            bytes: state.bytes,
+        }
+    }
+}
@@ -0,0 +1,252 @@
+use std::fmt;
+
+use time;
+
+use ::conditional;
+use ::listing;
+
+/// Freshness given to fingerprinted files, the longest RFC 7234 advises
+const ONE_YEAR: u64 = 365 * 24 * 60 * 60;
+
+/// Shortest run of hex digits taken for a fingerprint in a file name
+const MIN_FINGERPRINT_LENGTH: usize = 6;
+
+/// A `Cache-Control` response directive
+#[derive(Clone, Debug, PartialEq)]
+pub enum Directive {
+    Public,
+    Private,
+    NoCache,
+    NoStore,
+    MustRevalidate,
+    MaxAge(u64),
+    /// The file never changes while it is fresh, see RFC 8246
+    Immutable,
+}
+
+impl Directive {
+    pub fn parse(directive: &str) -> Option<Directive> {
+        let directive = directive.trim().to_lowercase();
+
+        if directive.starts_with("max-age=") {
+            return directive[8..].parse().ok().map(Directive::MaxAge);
+        }
+
+        match directive.as_ref() {
+            "public" => Some(Directive::Public),
+            "private" => Some(Directive::Private),
+            "no-cache" => Some(Directive::NoCache),
+            "no-store" => Some(Directive::NoStore),
+            "must-revalidate" => Some(Directive::MustRevalidate),
+            "immutable" => Some(Directive::Immutable),
+            _ => None,
+        }
+    }
+
+    /// Parse a comma-separated list of directives, as found in `Cache-Control`
+    pub fn parse_list(directives: &str) -> Option<Vec<Directive>> {
+        directives.split(',')
+            .filter(|d| !d.trim().is_empty())
+            .map(Directive::parse)
+            .collect()
+    }
+}
+
+impl fmt::Display for Directive {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Directive::Public => write!(f, "public"),
+            Directive::Private => write!(f, "private"),
+            Directive::NoCache => write!(f, "no-cache"),
+            Directive::NoStore => write!(f, "no-store"),
+            Directive::MustRevalidate => write!(f, "must-revalidate"),
+            Directive::MaxAge(seconds) => write!(f, "max-age={}", seconds),
+            Directive::Immutable => write!(f, "immutable"),
+        }
+    }
+}
+
+/// What a caching rule applies to
+#[derive(Clone, Debug, PartialEq)]
+pub enum Matcher {
+    /// Glob over the path of the file from the root, like `/assets/*.css`,
+    /// where `*` also matches slashes
+    Path(String),
+    /// Media type such as `text/html`, or a range such as `image/*`
+    MediaType(String),
+}
+
+impl Matcher {
+    /// Read a pattern as a path glob when it starts with `/` or `*`, and as
+    /// a media type otherwise
+    pub fn parse(pattern: &str) -> Matcher {
+        if pattern.starts_with('/') || pattern.starts_with('*') {
+            Matcher::Path(pattern.to_owned())
+        } else {
+            Matcher::MediaType(pattern.to_lowercase())
+        }
+    }
+
+    fn matches(&self, path: &str, media_type: &str) -> bool {
+        match *self {
+            Matcher::Path(ref pattern) => listing::glob_matches(pattern, path),
+            Matcher::MediaType(ref pattern) => {
+                let media_type = media_type.split(';').next().unwrap_or("").trim().to_lowercase();
+
+                if pattern.ends_with("/*") {
+                    media_type.starts_with(&pattern[..pattern.len() - 1])
+                } else {
+                    media_type == *pattern
+                }
+            },
+        }
+    }
+}
+
+/// Whether a file name carries a content hash, like `app.3f9a1c.js`, so
+/// that the file behind the name can never change
+///
+/// A hash mixes digits and letters, which tells it apart from the dates and
+/// build numbers found in names like `report.20261018.pdf`
+pub fn is_fingerprinted(name: &str) -> bool {
+    let parts: Vec<&str> = name.split('.').collect();
+    if parts.len() < 3 {
+        return false;
+    }
+
+    // neither the base name nor the extension
+    parts[1..parts.len() - 1].iter().any(|part| {
+        part.len() >= MIN_FINGERPRINT_LENGTH
+            && part.chars().all(|c| c.is_digit(16))
+            && part.chars().any(|c| c.is_digit(10))
+            && part.chars().any(|c| !c.is_digit(10))
+    })
+}
+
+/// Table of the caching directives sent with responses
+///
+/// The first rule matching the path or media type of a response wins.
+/// Without a matching rule fingerprinted files are cached for a year as
+/// immutable, and everything else gets the default, which is to send no
+/// caching headers at all
+#[derive(Clone, Debug)]
+pub struct CacheRules {
+    rules: Vec<(Matcher, Vec<Directive>)>,
+    fingerprinted: Vec<Directive>,
+    default: Vec<Directive>,
+}
+
+impl CacheRules {
+    pub fn new() -> CacheRules {
+        CacheRules {
+            rules: Vec::new(),
+            fingerprinted: vec![Directive::Public, Directive::MaxAge(ONE_YEAR), Directive::Immutable],
+            default: Vec::new(),
+        }
+    }
+
+    pub fn with_rule(&mut self, matcher: Matcher, directives: Vec<Directive>) -> &mut Self {
+        self.rules.push((matcher, directives));
+        self
+    }
+
+    /// Directives for fingerprinted files, an empty list treating them like any other
+    pub fn with_fingerprinted(&mut self, directives: Vec<Directive>) -> &mut Self {
+        self.fingerprinted = directives;
+        self
+    }
+
+    pub fn with_default(&mut self, directives: Vec<Directive>) -> &mut Self {
+        self.default = directives;
+        self
+    }
+
+    /// Directives for a response, `path` being the one of the file from the root
+    pub fn directives(&self, path: &str, media_type: &str) -> &[Directive] {
+        if let Some(&(_, ref directives)) = self.rules.iter().find(|&&(ref matcher, _)| matcher.matches(path, media_type)) {
+            return directives;
+        }
+
+        let name = path.rsplit('/').next().unwrap_or(path);
+        if !self.fingerprinted.is_empty() && is_fingerprinted(name) {
+            return &self.fingerprinted;
+        }
+
+        &self.default
+    }
+
+    /// `Cache-Control` and `Expires` headers for a response
+    pub fn headers(&self, path: &str, media_type: &str) -> Vec<(&'static str, String)> {
+        let directives = self.directives(path, media_type);
+        if directives.is_empty() {
+            return Vec::new();
+        }
+
+        let value: Vec<String> = directives.iter().map(|d| d.to_string()).collect();
+        let mut headers = vec![("Cache-Control", value.join(", "))];
+
+        // for HTTP/1.0 caches, which ignore Cache-Control
+        let max_age = directives.iter().filter_map(|d| match *d {
+            Directive::MaxAge(seconds) => Some(seconds),
+            _ => None,
+        }).next();
+
+        if directives.contains(&Directive::NoStore) || directives.contains(&Directive::NoCache) {
+            headers.push(("Expires", conditional::format_http_date(0)));
+        } else if let Some(max_age) = max_age {
+            let expires = time::get_time().sec + max_age as i64;
+            headers.push(("Expires", conditional::format_http_date(expires)));
+        }
+
+        headers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fingerprinted_names() {
+        for name in &["app.3f9a1c.js", "main.0123456789abcdef.css", "logo.A1B2C3D4.svg", "vendor.min.9e8d7c6b.js",
+                      "bundle.5f3e2a.min.js"] {
+            assert!(is_fingerprinted(name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn names_without_fingerprint() {
+        for name in &["app.js", "3f9a1c.js", "app.js.3f9a1c", "app.3f9a1.js", "my.facade.js", "my.deadbeef.js",
+                      "report.20261018.pdf", "backup.123456.tar", "archive.2026.10.18.zip", "app.3f9a1g.js"] {
+            assert!(!is_fingerprinted(name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn parse_directives() {
+        assert_eq!(Directive::parse_list("public, max-age=60, immutable"),
+                   Some(vec![Directive::Public, Directive::MaxAge(60), Directive::Immutable]));
+        assert_eq!(Directive::parse_list("No-Cache,"), Some(vec![Directive::NoCache]));
+        assert_eq!(Directive::parse_list("public, max-age=soon"), None);
+        assert_eq!(Directive::parse_list("stale-while-revalidate=5"), None);
+    }
+
+    #[test]
+    fn rules() {
+        let mut rules = CacheRules::new();
+        rules.with_rule(Matcher::parse("/assets/*"), vec![Directive::MaxAge(60)])
+            .with_rule(Matcher::parse("image/*"), vec![Directive::NoCache])
+            .with_default(vec![Directive::NoStore]);
+
+        assert_eq!(rules.directives("/assets/deep/app.css", "text/css"), &[Directive::MaxAge(60)]);
+        assert_eq!(rules.directives("/logo.png", "image/png"), &[Directive::NoCache]);
+        assert_eq!(rules.directives("/app.3f9a1c.js", "application/javascript"),
+                   &[Directive::Public, Directive::MaxAge(ONE_YEAR), Directive::Immutable]);
+        assert_eq!(rules.directives("/report.20261018.pdf", "application/pdf"), &[Directive::NoStore]);
+    }
+
+    #[test]
+    fn no_headers_by_default() {
+        assert!(CacheRules::new().headers("/report.20261018.pdf", "application/pdf").is_empty());
+    }
+}
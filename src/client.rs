@@ -0,0 +1,262 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{self, BufReader, Cursor, Read, Write, ErrorKind};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use url::Url;
+
+use ::headers::Headers;
+use ::parser::{Parser, ParserHandler, ParseError};
+
+#[derive(Default)]
+struct ClientParserHandler {
+    started: bool,
+    version: String,
+    status: u16,
+    reason: String,
+    headers: Headers,
+    body: Vec<u8>,
+}
+
+impl ClientParserHandler {
+    /// Whether the server left the connection open for another request
+    fn keep_alive(&self) -> bool {
+        let tokens: Vec<String> = match self.headers.find("Connection") {
+            Some(values) => values.iter().map(|v| v.to_lowercase()).collect(),
+            None => Vec::new(),
+        };
+
+        if self.version == "1.0" {
+            tokens.iter().any(|t| t == "keep-alive")
+        } else {
+            !tokens.iter().any(|t| t == "close")
+        }
+    }
+
+    /// Whether the end of the body was marked by closing the connection
+    fn delimited_by_close(&self) -> bool {
+        !(self.headers.has("Content-Length") || self.headers.has("Transfer-Encoding"))
+            && !(self.status / 100 == 1 || self.status == 204 || self.status == 304)
+    }
+}
+
+impl ParserHandler for ClientParserHandler {
+    fn on_message_begin(&mut self) -> Result<(), ParseError> {
+        self.started = true;
+        Ok(())
+    }
+
+    fn on_http_version(&mut self, version: &str) -> Result<(), ParseError> {
+        self.version = version.to_owned();
+        Ok(())
+    }
+
+    fn on_status(&mut self, status: u16) -> Result<(), ParseError> {
+        self.status = status;
+        Ok(())
+    }
+
+    fn on_reason(&mut self, reason: &str) -> Result<(), ParseError> {
+        self.reason = reason.to_owned();
+        Ok(())
+    }
+
+    fn on_header(&mut self, field: &str, values: Vec<&str>) -> Result<(), ParseError> {
+        for value in values {
+            self.headers.insert(field, value);
+        }
+        Ok(())
+    }
+
+    fn on_body(&mut self, part: &[u8]) -> Result<(), ParseError> {
+        self.body.extend(part.iter().cloned());
+        Ok(())
+    }
+}
+
+/// Response received by a `Client`
+#[derive(Debug)]
+pub struct Response {
+    http_version: String,
+    status: u16,
+    status_text: String,
+    headers: Headers,
+    body: Cursor<Vec<u8>>,
+}
+
+impl Response {
+    pub fn http_version(&self) -> &str {
+        self.http_version.as_ref()
+    }
+
+    pub fn status(&self) -> (u16, &str) {
+        (self.status, self.status_text.as_ref())
+    }
+
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    /// Reader over the response body, already decoded from its transfer framing
+    pub fn body<'a>(&'a mut self) -> &'a mut Read {
+        &mut self.body
+    }
+}
+
+/// Blocking HTTP/1.1 client
+///
+/// Connections are kept open after a response when the server allows it
+/// and reused for the next request to the same host and port
+///
+/// #Examples
+///
+/// ```no_run
+/// use std::io::Read;
+/// use mahardhika::client::Client;
+///
+/// let mut client = Client::new();
+/// let mut response = client.get("http://127.0.0.1:8000/index.html").unwrap();
+///
+/// let mut body = String::new();
+/// response.body().read_to_string(&mut body).unwrap();
+/// ```
+pub struct Client {
+    connections: HashMap<String, BufReader<TcpStream>>,
+    timeout: Option<Duration>,
+}
+
+impl Client {
+    pub fn new() -> Client {
+        Client {
+            connections: HashMap::new(),
+            timeout: None,
+        }
+    }
+
+    /// Set the read and write timeout of the connections, `None` waits indefinitely
+    pub fn with_timeout(&mut self, timeout: Option<Duration>) -> &mut Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn get(&mut self, url: &str) -> Result<Response, Box<Error>> {
+        self.request("GET", url, &Headers::new(), &[])
+    }
+
+    pub fn post(&mut self, url: &str, body: &[u8]) -> Result<Response, Box<Error>> {
+        self.request("POST", url, &Headers::new(), body)
+    }
+
+    /// Send a request and read the whole response
+    ///
+    /// `Host` and `Content-Length` are added unless given in `headers`
+    pub fn request(&mut self, method: &str, url: &str, headers: &Headers, body: &[u8]) -> Result<Response, Box<Error>> {
+        let url = try!(Url::parse(url));
+
+        if url.scheme != "http" {
+            let error = io::Error::new(ErrorKind::InvalidInput, format!("Unsupported scheme: '{}'", url.scheme));
+            return Err(Box::new(error));
+        }
+
+        let (host, port) = match (url.serialize_host(), url.port_or_default()) {
+            (Some(host), Some(port)) => (host, port),
+            _ => {
+                let error = io::Error::new(ErrorKind::InvalidInput, "Missing host in URL");
+                return Err(Box::new(error));
+            }
+        };
+        let addr = format!("{}:{}", host, port);
+
+        let mut target = url.serialize_path().unwrap_or("/".to_owned());
+        if let Some(ref query) = url.query {
+            target.push('?');
+            target.push_str(query);
+        }
+
+        let mut head = format!("{} {} HTTP/1.1\r\n", method, target);
+        if !headers.has("Host") {
+            match url.port() {
+                Some(port) => head.push_str(&format!("Host: {}:{}\r\n", host, port)),
+                None => head.push_str(&format!("Host: {}\r\n", host)),
+            }
+        }
+        if !headers.has("Content-Length") && (!body.is_empty() || method == "POST" || method == "PUT") {
+            head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+        }
+        head.push_str(&headers.to_string());
+        head.push_str("\r\n");
+
+        // a pooled connection may have been closed by the server in the
+        // meantime, in which case the request is retried on a new one
+        if let Some(stream) = self.connections.remove(&addr) {
+            if let Some(response) = try!(self.send(&addr, stream, method, head.as_bytes(), body, true)) {
+                return Ok(response);
+            }
+        }
+
+        let stream = try!(TcpStream::connect(&addr[..]));
+        try!(stream.set_read_timeout(self.timeout));
+        try!(stream.set_write_timeout(self.timeout));
+
+        match try!(self.send(&addr, BufReader::new(stream), method, head.as_bytes(), body, false)) {
+            Some(response) => Ok(response),
+            None => {
+                let error = io::Error::new(ErrorKind::UnexpectedEof, "Connection closed without response");
+                Err(Box::new(error))
+            }
+        }
+    }
+
+    /// Write the request and read the response, returning `None` when a
+    /// reused connection turns out to be closed
+    fn send(&mut self, addr: &str, mut stream: BufReader<TcpStream>, method: &str,
+            head: &[u8], body: &[u8], reused: bool) -> Result<Option<Response>, Box<Error>> {
+        let written = stream.get_mut().write_all(head)
+            .and_then(|_| stream.get_mut().write_all(body))
+            .and_then(|_| stream.get_mut().flush());
+
+        match written {
+            Ok(()) => {},
+            Err(_) if reused => return Ok(None),
+            Err(e) => return Err(Box::new(e)),
+        }
+
+        let mut handler = ClientParserHandler::default();
+
+        loop {
+            {
+                let mut parser = Parser::response(&mut handler);
+                parser.with_head_request(method == "HEAD");
+
+                match parser.parse(&mut stream) {
+                    Ok(()) => {},
+                    Err(_) if reused && parser.is_idle() => return Ok(None),
+                    Err(e) => return Err(e),
+                }
+            }
+
+            if !handler.started {
+                return Ok(None);
+            }
+
+            // interim responses are followed by the final one
+            if handler.status / 100 != 1 {
+                break;
+            }
+            handler = ClientParserHandler::default();
+        }
+
+        if handler.keep_alive() && !handler.delimited_by_close() {
+            self.connections.insert(addr.to_owned(), stream);
+        }
+
+        Ok(Some(Response {
+            http_version: handler.version,
+            status: handler.status,
+            status_text: handler.reason,
+            headers: handler.headers,
+            body: Cursor::new(handler.body),
+        }))
+    }
+}
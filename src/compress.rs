@@ -0,0 +1,166 @@
+use std::io::{self, Write};
+use std::mem;
+
+use flate2::Compression as Level;
+use flate2::write::{GzEncoder, ZlibEncoder};
+
+use ::handler::Handler;
+use ::negotiate;
+use ::request::Request;
+use ::response::Response;
+
+/// Bodies known to be smaller than this are not worth compressing
+const DEFAULT_MIN_SIZE: u64 = 1024;
+
+/// Media types whose content is already compressed, or whose own format
+/// compresses it
+const COMPRESSED_TYPES: &'static [&'static str] = &[
+    "application/gzip",
+    "application/octet-stream",
+    "application/pdf",
+    "application/vnd.rar",
+    "application/wasm",
+    "application/x-7z-compressed",
+    "application/x-bzip2",
+    "application/x-gzip",
+    "application/x-rar-compressed",
+    "application/x-tar",
+    "application/x-xz",
+    "application/zip",
+    "application/zstd",
+];
+
+/// Content codings the server compresses with, in order of preference
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Coding {
+    Gzip,
+    /// The zlib format, which is what `deflate` means in HTTP
+    Deflate,
+}
+
+impl Coding {
+    pub fn all() -> [Coding; 2] {
+        [Coding::Gzip, Coding::Deflate]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Coding::Gzip => "gzip",
+            Coding::Deflate => "deflate",
+        }
+    }
+}
+
+/// What the compression layer settled on for a response, applied by
+/// `Response::start` once the headers of the response are known
+#[derive(Clone, Copy, Debug)]
+pub struct Policy {
+    /// Coding the client accepts, `None` when it wants the body as is
+    pub coding: Option<Coding>,
+    pub min_size: u64,
+}
+
+/// Whether a body of the given media type gains anything from compression
+pub fn is_compressible(content_type: &str) -> bool {
+    let media_type = content_type.split(';').next().unwrap_or("").trim().to_lowercase();
+
+    if media_type == "image/svg+xml" {
+        return true;
+    }
+    if media_type.starts_with("image/") || media_type.starts_with("audio/")
+            || media_type.starts_with("video/") || media_type.starts_with("font/woff") {
+        return false;
+    }
+
+    !COMPRESSED_TYPES.contains(&media_type.as_ref())
+}
+
+/// Compressor for a response body, buffering its output until it is taken
+pub enum Encoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    Deflate(ZlibEncoder<Vec<u8>>),
+}
+
+impl Encoder {
+    pub fn new(coding: Coding) -> Encoder {
+        match coding {
+            Coding::Gzip => Encoder::Gzip(GzEncoder::new(Vec::new(), Level::Default)),
+            Coding::Deflate => Encoder::Deflate(ZlibEncoder::new(Vec::new(), Level::Default)),
+        }
+    }
+
+    /// Compress `buf`, returning whatever compressed data is ready
+    pub fn compress(&mut self, buf: &[u8]) -> Result<Vec<u8>, io::Error> {
+        match *self {
+            Encoder::Gzip(ref mut encoder) => {
+                try!(encoder.write_all(buf));
+                Ok(mem::replace(encoder.get_mut(), Vec::new()))
+            },
+            Encoder::Deflate(ref mut encoder) => {
+                try!(encoder.write_all(buf));
+                Ok(mem::replace(encoder.get_mut(), Vec::new()))
+            },
+        }
+    }
+
+    /// Push out everything written so far, so the client can decompress it
+    pub fn flush(&mut self) -> Result<Vec<u8>, io::Error> {
+        match *self {
+            Encoder::Gzip(ref mut encoder) => {
+                try!(encoder.flush());
+                Ok(mem::replace(encoder.get_mut(), Vec::new()))
+            },
+            Encoder::Deflate(ref mut encoder) => {
+                try!(encoder.flush());
+                Ok(mem::replace(encoder.get_mut(), Vec::new()))
+            },
+        }
+    }
+
+    /// End the compressed stream, returning its last bytes
+    pub fn finish(self) -> Result<Vec<u8>, io::Error> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.finish(),
+            Encoder::Deflate(encoder) => encoder.finish(),
+        }
+    }
+}
+
+/// Handler compressing the responses of another one on the fly
+///
+/// The coding is chosen from the `Accept-Encoding` header of the request,
+/// and bodies that are already compressed, partial or too small to gain
+/// anything are sent unchanged
+pub struct Compression {
+    inner: Box<Handler + Send + Sync>,
+    min_size: u64,
+}
+
+impl Compression {
+    pub fn new(inner: Box<Handler + Send + Sync>) -> Compression {
+        Compression {
+            inner: inner,
+            min_size: DEFAULT_MIN_SIZE,
+        }
+    }
+
+    /// Leave bodies with a `Content-Length` below `min_size` uncompressed
+    pub fn with_min_size(&mut self, min_size: u64) -> &mut Self {
+        self.min_size = min_size;
+        self
+    }
+}
+
+impl Handler for Compression {
+    fn handle_request(&self, req: &mut Request, res: &mut Response) -> Result<(), io::Error> {
+        let codings = Coding::all();
+        let names: Vec<&str> = codings.iter().map(|c| c.name()).collect();
+        let accept_encoding = req.headers().value("Accept-Encoding");
+
+        let coding = negotiate::best_encoding(accept_encoding.as_ref().map(|a| a.as_ref()), &names)
+            .map(|i| codings[i]);
+
+        res.with_compression(Policy { coding: coding, min_size: self.min_size });
+        self.inner.handle_request(req, res)
+    }
+}
@@ -0,0 +1,237 @@
+use std::fs::Metadata;
+use std::time::UNIX_EPOCH;
+
+use time::{self, Timespec};
+
+use ::headers::Headers;
+use ::request::Request;
+
+/// Outcome of evaluating the conditional headers of a request
+#[derive(Debug, PartialEq)]
+pub enum Precondition {
+    /// Handle the request normally
+    Proceed,
+    /// Answer with 304 Not Modified
+    NotModified,
+    /// Answer with 412 Precondition Failed
+    Failed,
+}
+
+/// Modification time of a file in whole seconds since the epoch
+pub fn modified_time(metadata: &Metadata) -> Option<i64> {
+    metadata.modified().ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_secs() as i64)
+}
+
+/// Format a time as an IMF-fixdate, the preferred HTTP date format
+pub fn format_http_date(seconds: i64) -> String {
+    time::at_utc(Timespec::new(seconds, 0)).rfc822().to_string()
+}
+
+pub fn parse_http_date(date: &str) -> Option<i64> {
+    time::strptime(date.trim(), "%a, %d %b %Y %T GMT").ok().map(|tm| tm.to_timespec().sec)
+}
+
+#[cfg(unix)]
+fn inode(metadata: &Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    metadata.ino()
+}
+
+#[cfg(not(unix))]
+fn inode(_metadata: &Metadata) -> u64 {
+    0
+}
+
+/// Strong entity tag of a file, built from its inode, size and modification time
+pub fn etag(metadata: &Metadata) -> String {
+    let modified = metadata.modified().ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_secs() * 1000000000 + duration.subsec_nanos() as u64)
+        .unwrap_or(0);
+
+    format!("\"{:x}-{:x}-{:x}\"", inode(metadata), metadata.len(), modified)
+}
+
+fn is_weak(tag: &str) -> bool {
+    tag.starts_with("W/")
+}
+
+fn opaque_tag(tag: &str) -> &str {
+    if is_weak(tag) { &tag[2..] } else { tag }
+}
+
+/// Whether an entity tag list matches, `*` matching any current representation
+fn tag_list_matches(list: &[&str], etag: &str, strong: bool) -> bool {
+    list.iter().any(|tag| {
+        if *tag == "*" {
+            return true;
+        }
+
+        if strong {
+            !is_weak(tag) && !is_weak(etag) && *tag == etag
+        } else {
+            opaque_tag(tag) == opaque_tag(etag)
+        }
+    })
+}
+
+/// Whether `If-Range` allows the `Range` header to be used, that is the
+/// validator it carries still matches the representation
+pub fn if_range_matches(req: &Request, etag: &str, modified: Option<i64>) -> bool {
+    if_range_header_matches(req.headers(), etag, modified)
+}
+
+fn if_range_header_matches(headers: &Headers, etag: &str, modified: Option<i64>) -> bool {
+    let value = match headers.value("If-Range") {
+        Some(value) => value,
+        None => return true,
+    };
+
+    let value = value.trim();
+    if value.starts_with("\"") || is_weak(value) {
+        return tag_list_matches(&[value], etag, true);
+    }
+
+    match (parse_http_date(value), modified) {
+        (Some(date), Some(modified)) => date == modified,
+        _ => false,
+    }
+}
+
+/// Evaluate the preconditions of a request against the validators of the
+/// selected representation, in the order given by RFC 7232 section 6
+pub fn evaluate(req: &Request, etag: &str, modified: Option<i64>) -> Precondition {
+    evaluate_headers(req.headers(), req.method(), etag, modified)
+}
+
+fn evaluate_headers(headers: &Headers, method: &str, etag: &str, modified: Option<i64>) -> Precondition {
+    let safe = method == "GET" || method == "HEAD";
+
+    if let Some(list) = headers.find("If-Match") {
+        if !tag_list_matches(&list, etag, true) {
+            return Precondition::Failed;
+        }
+    } else if let Some(date) = headers.value("If-Unmodified-Since").and_then(|d| parse_http_date(&d)) {
+        if let Some(modified) = modified {
+            if modified > date {
+                return Precondition::Failed;
+            }
+        }
+    }
+
+    if let Some(list) = headers.find("If-None-Match") {
+        if tag_list_matches(&list, etag, false) {
+            return if safe { Precondition::NotModified } else { Precondition::Failed };
+        }
+    } else if safe {
+        if let Some(date) = headers.value("If-Modified-Since").and_then(|d| parse_http_date(&d)) {
+            if let Some(modified) = modified {
+                if modified <= date {
+                    return Precondition::NotModified;
+                }
+            }
+        }
+    }
+
+    Precondition::Proceed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ETAG: &'static str = "\"1a-2b-3c\"";
+    const MODIFIED: i64 = 784111777;
+    const MODIFIED_DATE: &'static str = "Sun, 06 Nov 1994 08:49:37 GMT";
+
+    fn headers(fields: &[(&str, &str)]) -> Headers {
+        let mut headers = Headers::new();
+        for &(name, value) in fields {
+            headers.parse(&format!("{}: {}", name, value));
+        }
+        headers
+    }
+
+    fn evaluate_get(fields: &[(&str, &str)]) -> Precondition {
+        evaluate_headers(&headers(fields), "GET", ETAG, Some(MODIFIED))
+    }
+
+    #[test]
+    fn http_dates() {
+        assert_eq!(format_http_date(MODIFIED), MODIFIED_DATE);
+        assert_eq!(parse_http_date(MODIFIED_DATE), Some(MODIFIED));
+        assert_eq!(parse_http_date(" Sun, 06 Nov 1994 08:49:37 GMT "), Some(MODIFIED));
+        assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), None);
+    }
+
+    #[test]
+    fn no_preconditions() {
+        assert_eq!(evaluate_get(&[]), Precondition::Proceed);
+    }
+
+    #[test]
+    fn if_match() {
+        assert_eq!(evaluate_get(&[("If-Match", ETAG)]), Precondition::Proceed);
+        assert_eq!(evaluate_get(&[("If-Match", "*")]), Precondition::Proceed);
+        assert_eq!(evaluate_get(&[("If-Match", "\"other\", \"1a-2b-3c\"")]), Precondition::Proceed);
+        assert_eq!(evaluate_get(&[("If-Match", "\"other\"")]), Precondition::Failed);
+
+        // weak tags never match strongly
+        assert_eq!(evaluate_get(&[("If-Match", "W/\"1a-2b-3c\"")]), Precondition::Failed);
+    }
+
+    #[test]
+    fn if_unmodified_since() {
+        assert_eq!(evaluate_get(&[("If-Unmodified-Since", MODIFIED_DATE)]), Precondition::Proceed);
+        assert_eq!(evaluate_get(&[("If-Unmodified-Since", "Sat, 05 Nov 1994 08:49:37 GMT")]), Precondition::Failed);
+
+        // ignored when If-Match is present, or when the date is invalid
+        assert_eq!(evaluate_get(&[("If-Match", "*"), ("If-Unmodified-Since", "Sat, 05 Nov 1994 08:49:37 GMT")]),
+                   Precondition::Proceed);
+        assert_eq!(evaluate_get(&[("If-Unmodified-Since", "yesterday")]), Precondition::Proceed);
+    }
+
+    #[test]
+    fn if_none_match() {
+        assert_eq!(evaluate_get(&[("If-None-Match", ETAG)]), Precondition::NotModified);
+        assert_eq!(evaluate_get(&[("If-None-Match", "W/\"1a-2b-3c\"")]), Precondition::NotModified);
+        assert_eq!(evaluate_get(&[("If-None-Match", "*")]), Precondition::NotModified);
+        assert_eq!(evaluate_get(&[("If-None-Match", "\"other\"")]), Precondition::Proceed);
+
+        let headers = headers(&[("If-None-Match", ETAG)]);
+        assert_eq!(evaluate_headers(&headers, "HEAD", ETAG, Some(MODIFIED)), Precondition::NotModified);
+        assert_eq!(evaluate_headers(&headers, "PUT", ETAG, Some(MODIFIED)), Precondition::Failed);
+    }
+
+    #[test]
+    fn if_modified_since() {
+        assert_eq!(evaluate_get(&[("If-Modified-Since", MODIFIED_DATE)]), Precondition::NotModified);
+        assert_eq!(evaluate_get(&[("If-Modified-Since", "Sat, 05 Nov 1994 08:49:37 GMT")]), Precondition::Proceed);
+
+        // ignored when If-None-Match is present, for unsafe methods, or without a modification time
+        assert_eq!(evaluate_get(&[("If-None-Match", "\"other\""), ("If-Modified-Since", MODIFIED_DATE)]),
+                   Precondition::Proceed);
+        let headers = headers(&[("If-Modified-Since", MODIFIED_DATE)]);
+        assert_eq!(evaluate_headers(&headers, "POST", ETAG, Some(MODIFIED)), Precondition::Proceed);
+        assert_eq!(evaluate_headers(&headers, "GET", ETAG, None), Precondition::Proceed);
+    }
+
+    #[test]
+    fn if_match_is_evaluated_first() {
+        assert_eq!(evaluate_get(&[("If-Match", "\"other\""), ("If-None-Match", ETAG)]), Precondition::Failed);
+    }
+
+    #[test]
+    fn if_range() {
+        assert!(if_range_header_matches(&headers(&[]), ETAG, Some(MODIFIED)));
+        assert!(if_range_header_matches(&headers(&[("If-Range", ETAG)]), ETAG, Some(MODIFIED)));
+        assert!(!if_range_header_matches(&headers(&[("If-Range", "\"other\"")]), ETAG, Some(MODIFIED)));
+        assert!(!if_range_header_matches(&headers(&[("If-Range", "W/\"1a-2b-3c\"")]), ETAG, Some(MODIFIED)));
+
+        assert!(if_range_header_matches(&headers(&[("If-Range", MODIFIED_DATE)]), ETAG, Some(MODIFIED)));
+        assert!(!if_range_header_matches(&headers(&[("If-Range", "Sat, 05 Nov 1994 08:49:37 GMT")]), ETAG, Some(MODIFIED)));
+        assert!(!if_range_header_matches(&headers(&[("If-Range", MODIFIED_DATE)]), ETAG, None));
+    }
+}
@@ -0,0 +1,35 @@
+/// Whether a byte can appear unencoded in an RFC 5987 extended value
+fn is_attr_char(b: u8) -> bool {
+    match b {
+        b'a'...b'z' | b'A'...b'Z' | b'0'...b'9' => true,
+        b'!' | b'#' | b'$' | b'&' | b'+' | b'-' | b'.' | b'^' | b'_' | b'`' | b'|' | b'~' => true,
+        _ => false,
+    }
+}
+
+/// Name for the plain `filename` parameter, for clients that do not
+/// understand `filename*`, with anything that is not printable ASCII or
+/// would need escaping in a quoted string replaced
+fn ascii_fallback(filename: &str) -> String {
+    filename.chars().map(|c| {
+        if c.is_ascii() && !c.is_control() && c != '"' && c != '\\' { c } else { '_' }
+    }).collect()
+}
+
+/// Value of a `Content-Disposition` header asking the client to save the
+/// body as `filename`, following RFC 6266
+///
+/// The name is given both as plain ASCII and as UTF-8 in `filename*`, as
+/// RFC 5987 encodes it, so clients knowing the latter get the exact name
+pub fn attachment(filename: &str) -> String {
+    let mut encoded = String::new();
+    for &b in filename.as_bytes() {
+        if is_attr_char(b) {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", b));
+        }
+    }
+
+    format!("attachment; filename=\"{}\"; filename*=UTF-8''{}", ascii_fallback(filename), encoded)
+}
@@ -1,16 +1,36 @@
 use std::any::Any;
+use std::collections::HashMap;
+use std::fmt;
 use std::fs::{self, File, Metadata};
-use std::io::{self, Write, ErrorKind};
+use std::io::{self, Read, Write, ErrorKind};
 use std::marker::PhantomData;
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::sync::Arc;
 
 use conduit_mime_types::Types;
-use url::percent_encoding as perc_enc;
+use time;
 
-use ::response::Response;
+use ::access::{self, AccessPolicy};
+use ::archive::{self, Kind};
+use ::cache::{CachedFile, FileCache};
+use ::cache_control::CacheRules;
+use ::conditional::{self, Precondition};
+use ::disposition;
+use ::listing::{self, Format, View};
+use ::negotiate;
+use ::range::{self, Ranges};
+use ::resolve::{self, SymlinkPolicy};
+use ::response::{BodyWriter, Response};
 use ::request::Request;
 
+/// Content codings of precompressed files, with the suffix of the file
+/// holding each one next to the original, in order of preference
+const PRECOMPRESSED: &'static [(&'static str, &'static str)] = &[
+    ("br", ".br"),
+    ("zstd", ".zst"),
+    ("gzip", ".gz"),
+];
+
 pub struct FileMode;
 pub struct DirectoryMode;
 
@@ -18,10 +38,57 @@ pub trait Handler {
     fn handle_request(&self, req: &mut Request, res: &mut Response) -> Result<(), io::Error>;
 }
 
+/// What `ServerHandler` does for a request method
+enum Method {
+    /// Serve the resource, the body being dropped for `HEAD`
+    Serve,
+    /// List the allowed methods
+    Options,
+    /// Pass the request on to another handler
+    Custom(Box<Handler + Send + Sync>),
+}
+
+impl fmt::Debug for Method {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Method::Serve => write!(f, "Serve"),
+            Method::Options => write!(f, "Options"),
+            Method::Custom(_) => write!(f, "Custom"),
+        }
+    }
+}
+
+/// Where the bytes of a file being sent come from
+enum Content {
+    File(File),
+    Cached(Arc<CachedFile>),
+}
+
+impl Content {
+    fn copy_range(&mut self, res: &mut BodyWriter, start: u64, len: u64) -> Result<(), io::Error> {
+        match *self {
+            Content::File(ref mut f) => res.copy_file(f, start, len),
+            Content::Cached(ref cached) => res.write_all(&cached.body[start as usize..(start + len) as usize]),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct ServerHandler<M: Any> {
     root: PathBuf,
     mimetypes: Types,
+    methods: Vec<(String, Method)>,
+    symlinks: SymlinkPolicy,
+    index_files: Vec<String>,
+    cache: Option<Arc<FileCache>>,
+    cache_rules: CacheRules,
+    access: AccessPolicy,
+    error_pages: HashMap<i32, PathBuf>,
+    error_pages_dir: Option<PathBuf>,
+    spa_fallback: Option<PathBuf>,
+    clean_extensions: Vec<String>,
+    clean_url_redirect: bool,
+    downloads: Vec<String>,
     _kind: PhantomData<M>,
 }
 
@@ -32,18 +99,171 @@ impl<M: Any> ServerHandler<M> {
             Err(error) => panic!(error),
         };
 
+        let methods = vec![
+            ("GET".to_owned(), Method::Serve),
+            ("HEAD".to_owned(), Method::Serve),
+            ("OPTIONS".to_owned(), Method::Options),
+        ];
+
         ServerHandler {
             root: root.to_owned(),
             mimetypes: mimetypes,
+            methods: methods,
+            symlinks: SymlinkPolicy::Follow,
+            index_files: vec!["index.html".to_owned()],
+            cache: None,
+            cache_rules: CacheRules::new(),
+            access: AccessPolicy::new(),
+            error_pages: HashMap::new(),
+            error_pages_dir: None,
+            spa_fallback: None,
+            clean_extensions: Vec::new(),
+            clean_url_redirect: false,
+            downloads: Vec::new(),
             _kind: PhantomData
         }
     }
 
-    fn get_resource_and_metadata(&self, req: &Request) -> Result<(PathBuf, Metadata), io::Error> {
-        let mut resource = Path::new(&self.root).to_path_buf();
+    pub fn with_symlink_policy(&mut self, symlinks: SymlinkPolicy) -> &mut Self {
+        self.symlinks = symlinks;
+        self
+    }
+
+    /// Files served for a directory request, the first one found being used
+    pub fn with_index_files(&mut self, names: &[&str]) -> &mut Self {
+        self.index_files = names.iter().map(|name| name.to_string()).collect();
+        self
+    }
+
+    /// Keep small files in memory, the caller holding on to `cache` to
+    /// read its counters
+    pub fn with_cache(&mut self, cache: Arc<FileCache>) -> &mut Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Decide the `Cache-Control` and `Expires` headers sent with responses
+    pub fn with_cache_rules(&mut self, rules: CacheRules) -> &mut Self {
+        self.cache_rules = rules;
+        self
+    }
+
+    /// Decide which files are hidden, from requests and from listings
+    pub fn with_access_policy(&mut self, access: AccessPolicy) -> &mut Self {
+        self.access = access;
+        self
+    }
+
+    /// Send the file at `path` as the body of responses with `status`
+    pub fn with_error_page(&mut self, status: i32, path: &Path) -> &mut Self {
+        self.error_pages.insert(status, path.to_path_buf());
+        self
+    }
+
+    /// Look for error documents named after their status, like `404.html`,
+    /// in `dir`
+    ///
+    /// Pages given with `with_error_page` take precedence
+    pub fn with_error_pages_dir(&mut self, dir: &Path) -> &mut Self {
+        self.error_pages_dir = Some(dir.to_path_buf());
+        self
+    }
+
+    /// Answer page requests for missing paths with `document`, for
+    /// single-page applications routing on the client side
+    ///
+    /// Only `GET` and `HEAD` requests whose last path segment has no
+    /// extension and whose `Accept` header names `text/html` fall back, so
+    /// a missing asset still gets a 404
+    pub fn with_spa_fallback(&mut self, document: &Path) -> &mut Self {
+        self.spa_fallback = Some(document.to_path_buf());
+        self
+    }
 
-        for p in req.path_components().iter() {
-            resource = resource.join(p);
+    /// Serve `/about` from `about.html` when `html` is one of `extensions`
+    /// and no file matches the path exactly
+    pub fn with_clean_urls(&mut self, extensions: &[&str]) -> &mut Self {
+        self.clean_extensions = extensions.iter().map(|e| e.trim_left_matches('.').to_owned()).collect();
+        self
+    }
+
+    /// Redirect `/about.html` to `/about` so each page has a single URL
+    pub fn with_clean_url_redirect(&mut self, redirect: bool) -> &mut Self {
+        self.clean_url_redirect = redirect;
+        self
+    }
+
+    /// Have clients save files matching `pattern` rather than display them,
+    /// as the `download` query parameter does for a single request
+    ///
+    /// The pattern is matched against the path from the root when it holds
+    /// a slash, and against the file name otherwise
+    pub fn with_download(&mut self, pattern: &str) -> &mut Self {
+        self.downloads.push(pattern.to_owned());
+        self
+    }
+
+    /// Handle requests for `method` with `handler`
+    ///
+    /// This adds a method to the ones the server answers, or replaces how
+    /// one of them is handled
+    pub fn with_method(&mut self, method: &str, handler: Box<Handler + Send + Sync>) -> &mut Self {
+        self.methods.retain(|&(ref name, _)| name != method);
+        self.methods.push((method.to_owned(), Method::Custom(handler)));
+        self
+    }
+
+    /// Methods the server answers, in the order they are listed in `Allow`
+    pub fn allowed_methods(&self) -> Vec<&str> {
+        self.methods.iter().map(|&(ref name, _)| name.as_ref()).collect()
+    }
+
+    /// Look the request method up in the method table, `serve` being what
+    /// `GET` and `HEAD` do in the handler's mode
+    fn dispatch<F>(&self, req: &mut Request, res: &mut Response, serve: F) -> Result<(), io::Error>
+            where F: FnOnce(&Self, &mut Request, &mut Response) -> Result<(), io::Error> {
+        let method = self.methods.iter().find(|&&(ref name, _)| name == req.method());
+
+        match method {
+            Some(&(_, Method::Serve)) => serve(self, req, res),
+            Some(&(_, Method::Options)) => {
+                res.with_header("Allow", &self.allowed_methods().join(", "))
+                    .with_header("Content-Length", "0");
+                res.start(|_| Ok(()))
+            },
+            Some(&(_, Method::Custom(ref handler))) => handler.handle_request(req, res),
+            None => {
+                res.with_header("Allow", &self.allowed_methods().join(", "));
+                self.send_error(req, res, 405, "Method Not Allowed")
+            },
+        }
+    }
+
+    /// Path of a resolved file from the root, starting with a slash
+    fn relative_path(&self, resource: &Path) -> String {
+        slash_path(resource.strip_prefix(&self.root).unwrap_or(resource))
+    }
+
+    /// Whether the access policy lets `resource` be served, both by the path
+    /// it was asked for and by the file a symbolic link leads to
+    fn is_visible(&self, resource: &Path) -> bool {
+        if !self.access.is_visible(&self.relative_path(resource)) {
+            return false;
+        }
+
+        // missing files and links out of the root are refused when resolved
+        match resolve::target(&self.root, resource) {
+            Ok(target) => self.access.is_visible(&slash_path(&target)),
+            Err(_) => true,
+        }
+    }
+
+    fn resolve_visible(&self, components: &[&str]) -> Result<(PathBuf, Metadata), io::Error> {
+        let resource = try!(resolve::resolve(&self.root, components, self.symlinks));
+
+        // hidden files are not told apart from missing ones
+        if !self.is_visible(&resource) {
+            return Err(io::Error::new(ErrorKind::NotFound, "Hidden file"));
         }
 
         let metadata = try!(fs::metadata(&resource));
@@ -51,117 +271,591 @@ impl<M: Any> ServerHandler<M> {
         Ok((resource, metadata))
     }
 
-    fn send_file(&self, resource: &Path, metadata: &Metadata, res: &mut Response) -> Result<(), io::Error> {
-        let mut f = try!(File::open(&resource));
-        let mime = self.mimetypes.mime_for_path(Path::new(&resource));
+    fn get_resource_and_metadata(&self, req: &Request) -> Result<(PathBuf, Metadata), io::Error> {
+        let components = req.raw_path_components();
 
-        res.with_header("Content-Type", mime)
-            .with_header("Content-Length", &metadata.len().to_string());
+        let error = match self.resolve_visible(&components) {
+            Ok(found) => return Ok(found),
+            Err(error) => error,
+        };
 
-        res.start(|res| {
-            try!(io::copy(&mut f, res));
-            try!(res.flush());
-            Ok(())
-        })
+        if error.kind() != ErrorKind::NotFound {
+            return Err(error);
+        }
+
+        // a clean URL names a file without its extension
+        let last = components.last().cloned().unwrap_or("");
+        if !last.is_empty() {
+            for extension in &self.clean_extensions {
+                let name = format!("{}.{}", last, extension);
+                let mut candidate = components.clone();
+                candidate.pop();
+                candidate.push(&name);
+
+                if let Ok((resource, metadata)) = self.resolve_visible(&candidate) {
+                    if metadata.is_file() {
+                        return Ok((resource, metadata));
+                    }
+                }
+            }
+        }
+
+        Err(error)
+    }
+
+    /// Extensionless path to redirect a request to, when clean URL
+    /// redirects are on and the path ends with one of their extensions
+    fn clean_url_location(&self, req: &Request) -> Option<String> {
+        if !self.clean_url_redirect {
+            return None;
+        }
+
+        let mut components = req.raw_path_components();
+        let last = components.pop().unwrap_or("");
+
+        let stem = self.clean_extensions.iter()
+            .filter_map(|extension| {
+                let suffix = format!(".{}", extension);
+                if last.len() > suffix.len() && last.ends_with(&suffix) {
+                    Some(&last[..last.len() - suffix.len()])
+                } else {
+                    None
+                }
+            })
+            .next();
+
+        let stem = match stem {
+            Some(stem) => stem,
+            None => return None,
+        };
+        components.push(stem);
+
+        // a file or directory named like the stem would shadow the page
+        match self.resolve_visible(&components) {
+            Err(ref e) if e.kind() == ErrorKind::NotFound => Some(format!("/{}", components.join("/"))),
+            _ => None,
+        }
+    }
+
+    /// Find the index file of the requested directory, resolved like any
+    /// other path so the symbolic link policy applies to it as well
+    fn find_index(&self, req: &Request) -> Option<(PathBuf, Metadata)> {
+        let mut components = req.raw_path_components();
+        if components.last() == Some(&"") {
+            components.pop();
+        }
+
+        for name in &self.index_files {
+            let encoded = listing::encode_segment(name.as_bytes());
+            let mut candidate = components.clone();
+            candidate.push(&encoded);
+
+            let found = resolve::resolve(&self.root, &candidate, self.symlinks)
+                .and_then(|resource| fs::metadata(&resource).map(|metadata| (resource, metadata)));
+
+            if let Ok((resource, metadata)) = found {
+                if metadata.is_file() && self.is_visible(&resource) {
+                    return Some((resource, metadata));
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Whether the request path ends with a slash, as a directory path has
+    /// to for relative links to resolve inside it
+    fn has_trailing_slash(req: &Request) -> bool {
+        req.raw_path_components().last().map(|c| c.is_empty()).unwrap_or(true)
     }
 
-    fn send_not_found(&self, res: &mut Response) -> Result<(), io::Error> {
-        res.with_status(404, "Not Found");
+    /// Redirect a directory request to the same path with a trailing slash
+    fn send_directory_redirect(&self, req: &Request, res: &mut Response) -> Result<(), io::Error> {
+        let location = format!("/{}/", req.raw_path_components().join("/"));
+        self.send_redirect(req, res, &location)
+    }
+
+    /// Permanently redirect to `path`, keeping the query string
+    fn send_redirect(&self, req: &Request, res: &mut Response, path: &str) -> Result<(), io::Error> {
+        let mut location = path.to_owned();
+        if let Some(query) = req.query().as_ref().and_then(|query| query.query_string()) {
+            location.push('?');
+            location.push_str(query);
+        }
+
+        res.with_header("Location", &location);
+        self.send_error(req, res, 301, "Moved Permanently")
+    }
+
+    /// Precompressed versions of `resource` lying next to it, with their coding
+    fn find_precompressed(&self, resource: &Path) -> Vec<(&'static str, PathBuf, Metadata)> {
+        PRECOMPRESSED.iter().filter_map(|&(coding, suffix)| {
+            let sibling = match resolve::sibling(&self.root, resource, suffix, self.symlinks) {
+                Ok(ref sibling) if !self.is_visible(sibling) => return None,
+                Ok(sibling) => sibling,
+                Err(_) => return None,
+            };
+
+            match fs::metadata(&sibling) {
+                Ok(ref metadata) if metadata.is_file() => Some((coding, sibling.clone(), metadata.clone())),
+                _ => None,
+            }
+        }).collect()
+    }
+
+    /// Add the caching headers the rules give for `resource`
+    fn add_cache_headers(&self, res: &mut Response, resource: &Path, media_type: &str) {
+        let path = self.relative_path(resource);
+
+        for (name, value) in self.cache_rules.headers(&path, media_type) {
+            res.with_header(name, &value);
+        }
+    }
+
+    /// Ask the client to save `resource` when the request or the download
+    /// rules call for it
+    fn add_disposition(&self, req: &Request, res: &mut Response, resource: &Path) {
+        let requested = req.query().as_ref().map(|query| query.get("download").is_some()).unwrap_or(false);
+
+        let path = self.relative_path(resource);
+        if !requested && !self.downloads.iter().any(|pattern| access::pattern_matches(pattern, &path)) {
+            return;
+        }
+
+        let name = resource.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        res.with_header("Content-Disposition", &disposition::attachment(&name));
+    }
+
+    /// Get a small file from the cache, reading it in when it is missing or
+    /// out of date
+    ///
+    /// The file is cached under the path that was asked for, `resource`
+    /// being the file sent for it with the content coding `coding`
+    fn cached_file(&self, requested: &Path, coding: Option<&str>, resource: &Path, metadata: &Metadata) -> Option<Arc<CachedFile>> {
+        let cache = match self.cache {
+            Some(ref cache) if cache.accepts(metadata) => cache,
+            _ => return None,
+        };
+
+        if let Some(cached) = cache.get(requested, coding, metadata) {
+            return Some(cached);
+        }
+
+        let mut f = match File::open(resource) {
+            Ok(f) => f,
+            Err(_) => return None,
+        };
+
+        let mut body = Vec::with_capacity(metadata.len() as usize);
+        if f.read_to_end(&mut body).is_err() {
+            return None;
+        }
+
+        // a file changed while it was read is left for a later request
+        let etag = conditional::etag(metadata);
+        match f.metadata() {
+            Ok(ref current) if conditional::etag(current) == etag && current.len() == body.len() as u64 => {},
+            _ => return None,
+        }
+
+        let cached = Arc::new(CachedFile {
+            body: body,
+            mime: self.mimetypes.mime_for_path(requested).to_owned(),
+            etag: etag,
+            modified: conditional::modified_time(metadata),
+        });
+        cache.insert(requested, coding, cached.clone());
+
+        Some(cached)
+    }
+
+    /// Send the file a request resolved to, unless its URL is not the canonical one
+    fn serve_file(&self, req: &Request, resource: &Path, metadata: &Metadata, res: &mut Response) -> Result<(), io::Error> {
+        if let Some(location) = self.clean_url_location(req) {
+            return self.send_redirect(req, res, &location);
+        }
+
+        self.send_file(req, resource, metadata, res)
+    }
+
+    fn send_file(&self, req: &Request, resource: &Path, metadata: &Metadata, res: &mut Response) -> Result<(), io::Error> {
+        let requested = resource;
+
+        let precompressed = self.find_precompressed(resource);
+        if !precompressed.is_empty() {
+            res.with_header("Vary", "Accept-Encoding");
+        }
+
+        let codings: Vec<&str> = precompressed.iter().map(|&(coding, _, _)| coding).collect();
+        let accept_encoding = req.headers().value("Accept-Encoding");
+
+        let (resource, metadata, coding) = match negotiate::best_encoding(accept_encoding.as_ref().map(|a| a.as_ref()), &codings) {
+            Some(i) => {
+                let (coding, ref path, ref metadata) = precompressed[i];
+                (path.as_path(), metadata, Some(coding))
+            },
+            None => (resource, metadata, None),
+        };
+
+        // the type is the one of the original, whichever file is sent
+        let (mut content, mime, etag, modified, length) = match self.cached_file(requested, coding, resource, metadata) {
+            Some(cached) => {
+                let (mime, etag, modified, length) = (cached.mime.clone(), cached.etag.clone(), cached.modified, cached.body.len() as u64);
+                (Content::Cached(cached), mime, etag, modified, length)
+            },
+            None => {
+                let f = try!(File::open(&resource));
+                let mime = self.mimetypes.mime_for_path(requested).to_owned();
+                (Content::File(f), mime, conditional::etag(metadata), conditional::modified_time(metadata), metadata.len())
+            },
+        };
+        let mime: &str = &mime;
+
+        res.with_header("Accept-Ranges", "bytes")
+            .with_header("ETag", &etag);
+
+        if let Some(modified) = modified {
+            res.with_header("Last-Modified", &conditional::format_http_date(modified));
+        }
+
+        match conditional::evaluate(req, &etag, modified) {
+            Precondition::Proceed => {},
+            Precondition::NotModified => {
+                self.add_cache_headers(res, requested, mime);
+                res.with_status(304, "Not Modified");
+                return res.start(|_| Ok(()));
+            },
+            Precondition::Failed => return self.send_error(req, res, 412, "Precondition Failed"),
+        }
+
+        let ranges = match req.headers().value("Range") {
+            Some(ref header) if req.method() == "GET" && conditional::if_range_matches(req, &etag, modified) => {
+                range::parse(header, length)
+            },
+            _ => Ranges::Full,
+        };
+
+        if ranges == Ranges::Unsatisfiable {
+            res.with_header("Content-Range", &format!("bytes */{}", length));
+            return self.send_error(req, res, 416, "Range Not Satisfiable");
+        }
+
+        // describing the file, so not sent with the error responses above
+        if let Some(coding) = coding {
+            res.with_header("Content-Encoding", coding);
+        }
+        self.add_cache_headers(res, requested, mime);
+        self.add_disposition(req, res, requested);
+
+        let ranges = match ranges {
+            Ranges::Full => {
+                res.with_header("Content-Type", mime)
+                    .with_header("Content-Length", &length.to_string());
+
+                if req.method() == "HEAD" {
+                    return res.start(|_| Ok(()));
+                }
+
+                return res.start(|res| {
+                    try!(content.copy_range(res, 0, length));
+                    try!(res.flush());
+                    Ok(())
+                });
+            },
+            Ranges::Satisfiable(ranges) => ranges,
+            Ranges::Unsatisfiable => unreachable!(),
+        };
+
+        res.with_status(206, "Partial Content");
+
+        if ranges.len() == 1 {
+            let range = ranges[0];
+            res.with_header("Content-Type", mime)
+                .with_header("Content-Range", &range.content_range(length))
+                .with_header("Content-Length", &range.len().to_string());
+
+            return res.start(|res| {
+                try!(content.copy_range(res, range.start, range.len()));
+                try!(res.flush());
+                Ok(())
+            });
+        }
+
+        // several ranges are sent as the parts of a multipart/byteranges body
+        let boundary = format!("{:016x}", time::precise_time_ns());
+        let part_headers: Vec<String> = ranges.iter().map(|range| {
+            format!("\r\n--{}\r\nContent-Type: {}\r\nContent-Range: {}\r\n\r\n",
+                    boundary, mime, range.content_range(length))
+        }).collect();
+        let closing = format!("\r\n--{}--\r\n", boundary);
+
+        let content_length = ranges.iter().zip(part_headers.iter())
+            .fold(closing.len() as u64, |total, (range, part)| total + part.len() as u64 + range.len());
+
+        res.with_header("Content-Type", &format!("multipart/byteranges; boundary={}", boundary))
+            .with_header("Content-Length", &content_length.to_string());
+
         res.start(|res| {
-            try!(res.write("404 - Not Found".as_bytes()));
+            for (range, part) in ranges.iter().zip(part_headers.iter()) {
+                try!(res.write_all(part.as_bytes()));
+                try!(content.copy_range(res, range.start, range.len()));
+            }
+            try!(res.write_all(closing.as_bytes()));
             try!(res.flush());
             Ok(())
         })
     }
 
-    fn send_error(&self, res: &mut Response, status: i32, description: &str) -> Result<(), io::Error> {
-        res.with_status(status, description);
+    fn send_not_found(&self, req: &Request, res: &mut Response) -> Result<(), io::Error> {
+        self.send_error(req, res, 404, "Not Found")
+    }
+
+    /// The single-page application document, when `req` is a page request
+    /// it should answer
+    fn spa_document(&self, req: &Request) -> Option<(&Path, Metadata)> {
+        let document = match self.spa_fallback {
+            Some(ref document) => document,
+            None => return None,
+        };
+
+        if req.method() != "GET" && req.method() != "HEAD" {
+            return None;
+        }
+
+        let components = req.path_components();
+        let last = components.last().cloned().unwrap_or("");
+        if last.contains('.') {
+            return None;
+        }
+
+        let accept = req.headers().value("Accept");
+        if !negotiate::names(accept.as_ref().map(|a| a.as_ref()), "text/html") {
+            return None;
+        }
+
+        match fs::metadata(document) {
+            Ok(ref metadata) if metadata.is_file() => Some((document, metadata.clone())),
+            _ => None,
+        }
+    }
+
+    /// Answer with the status matching an error met while looking for the resource
+    fn send_io_error(&self, req: &Request, res: &mut Response, error: &io::Error) -> Result<(), io::Error> {
+        if error.kind() == ErrorKind::NotFound {
+            if let Some((document, metadata)) = self.spa_document(req) {
+                // other requests for the same path get a 404
+                res.with_header("Vary", "Accept");
+                return self.send_file(req, document, &metadata, res);
+            }
+        }
+
+        match error.kind() {
+            ErrorKind::NotFound => self.send_not_found(req, res),
+            ErrorKind::InvalidInput => self.send_error(req, res, 400, "Bad Request"),
+            ErrorKind::PermissionDenied => self.send_error(req, res, 403, "Forbidden"),
+            _ => self.send_error(req, res, 500, "Internal Server Error"),
+        }
+    }
+
+    /// Body and media type of the error document configured for `status`
+    fn error_document(&self, status: i32) -> Option<(Vec<u8>, String)> {
+        let path = match (self.error_pages.get(&status), self.error_pages_dir.as_ref()) {
+            (Some(path), _) => path.clone(),
+            (None, Some(dir)) => dir.join(format!("{}.html", status)),
+            (None, None) => return None,
+        };
+
+        let mut body = Vec::new();
+        match File::open(&path).and_then(|mut f| f.read_to_end(&mut body)) {
+            Ok(_) => Some((body, self.mimetypes.mime_for_path(&path).to_owned())),
+            Err(_) => None,
+        }
+    }
+
+    /// Answer with an error status, the body being the configured error
+    /// document or else a short one in the format the client prefers
+    fn send_error(&self, req: &Request, res: &mut Response, status: i32, description: &str) -> Result<(), io::Error> {
+        let (body, content_type) = match self.error_document(status) {
+            Some(document) => document,
+            None => {
+                res.with_header("Vary", "Accept");
+                error_body(req, status, description)
+            },
+        };
+
+        res.with_status(status, description)
+            .with_header("Content-Type", &content_type)
+            .with_header("Content-Length", &body.len().to_string());
+
         res.start(|res| {
-            try!(res.write(format!("{} - {}", status, description).as_bytes()));
+            try!(res.write_all(&body));
             try!(res.flush());
             Ok(())
         })
     }
 }
 
-impl Handler for ServerHandler<FileMode> {
-    fn handle_request(&self, req: &mut Request, res: &mut Response) -> Result<(), io::Error> {
+/// Path relative to the root as the access policy takes it, starting with a slash
+fn slash_path(relative: &Path) -> String {
+    let components: Vec<String> = relative.components()
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .collect();
+
+    format!("/{}", components.join("/"))
+}
+
+/// Default body of an error response, in plain text, HTML or JSON
+/// according to the `Accept` header
+fn error_body(req: &Request, status: i32, description: &str) -> (Vec<u8>, String) {
+    let media_types = ["text/plain", "text/html", "application/json"];
+
+    let accept = req.headers().value("Accept");
+    let chosen = negotiate::best_match(accept.as_ref().map(|a| a.as_ref()), &media_types).unwrap_or(0);
+
+    let body = match media_types[chosen] {
+        "text/html" => {
+            let title = listing::escape_html(&format!("{} {}", status, description));
+            format!("<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{0}</title></head>\n\
+                     <body><h1>{0}</h1></body></html>\n", title)
+        },
+        "application/json" => format!("{{\"status\":{},\"error\":\"{}\"}}", status, description),
+        _ => format!("{} - {}", status, description),
+    };
+
+    (body.into_bytes(), format!("{}; charset=utf-8", media_types[chosen]))
+}
+
+/// Format of a directory listing, from the `format` query parameter or
+/// else the `Accept` header, falling back to HTML
+fn listing_format(req: &Request) -> Format {
+    let requested = req.query().as_ref()
+        .and_then(|query| query.get("format"))
+        .and_then(|values| Format::from_name(&values[0]));
+
+    if let Some(format) = requested {
+        return format;
+    }
+
+    let formats = Format::all();
+    let media_types: Vec<&str> = formats.iter().map(|f| f.media_type()).collect();
+
+    match negotiate::best_match(req.headers().value("Accept").as_ref().map(|a| a.as_ref()), &media_types) {
+        Some(i) => formats[i],
+        None => Format::Html,
+    }
+}
+
+impl ServerHandler<FileMode> {
+    fn serve(&self, req: &mut Request, res: &mut Response) -> Result<(), io::Error> {
         let (resource, metadata) = match self.get_resource_and_metadata(req) {
             Ok(result) => result,
-            Err(e) => {
-                if e.kind() == ErrorKind::NotFound {
-                    return self.send_not_found(res);
-                } else {
-                    return self.send_error(res, 500, "Internal Server Error");
-                }
-            }
+            Err(e) => return self.send_io_error(req, res, &e),
         };
 
-        if !metadata.is_file() {
-            return self.send_not_found(res);
+        if metadata.is_file() {
+            return self.serve_file(req, &resource, &metadata, res);
         }
 
-        self.send_file(&resource, &metadata, res)
+        // without a listing to fall back on, only directories with an index exist
+        match self.find_index(req) {
+            Some(_) if !Self::has_trailing_slash(req) => self.send_directory_redirect(req, res),
+            Some((index, metadata)) => self.send_file(req, &index, &metadata, res),
+            None => self.send_not_found(req, res),
+        }
     }
 }
 
-impl Handler for ServerHandler<DirectoryMode> {
+impl Handler for ServerHandler<FileMode> {
     fn handle_request(&self, req: &mut Request, res: &mut Response) -> Result<(), io::Error> {
+        self.dispatch(req, res, ServerHandler::<FileMode>::serve)
+    }
+}
+
+impl ServerHandler<DirectoryMode> {
+    fn serve(&self, req: &mut Request, res: &mut Response) -> Result<(), io::Error> {
         let (resource, metadata) = match self.get_resource_and_metadata(req) {
             Ok(result) => result,
-            Err(e) => {
-                if e.kind() == ErrorKind::NotFound {
-                    return self.send_not_found(res);
-                } else {
-                    return self.send_error(res, 500, "Internal Server Error");
-                }
-            }
+            Err(e) => return self.send_io_error(req, res, &e),
         };
 
         if metadata.is_file() {
-            return self.send_file(&resource, &metadata, res);
+            return self.serve_file(req, &resource, &metadata, res);
+        }
+
+        if !Self::has_trailing_slash(req) {
+            return self.send_directory_redirect(req, res);
         }
 
-        let output = Command::new("ls")
-            .arg(&resource)
-            .output()
-            .unwrap_or_else(|e| panic!(format!("Failed to list dir: {}", e)));
+        let requested = req.query().as_ref()
+            .and_then(|query| query.get("archive"))
+            .map(|values| values[0].clone());
 
-        let s: String;
-        if output.status.success() {
-            s = String::from_utf8_lossy(&output.stdout).as_ref().to_owned();
-        } else {
-            s = String::from_utf8_lossy(&output.stderr).as_ref().to_owned();
-            panic!("rustc failed and stderr was:\n{}", s);
+        if let Some(name) = requested {
+            return match Kind::from_name(&name) {
+                Some(kind) => self.send_archive(req, res, &resource, kind),
+                None => self.send_error(req, res, 400, "Bad Request"),
+            };
         }
 
-        res.with_header("Content-Type", "text/html; charset=utf-8");
+        if let Some((index, metadata)) = self.find_index(req) {
+            return self.send_file(req, &index, &metadata, res);
+        }
 
-        res.start(|res| {
-            try!(res.write("<html><body><ul>".as_bytes()));
-            for name in s.split('\n') {
-                if name.len() == 0 { continue }
-                let mut name = name.to_owned();
+        let entries = match listing::read_entries(&resource) {
+            Ok(entries) => entries,
+            Err(e) => return self.send_io_error(req, res, &e),
+        };
 
-                let metadata = try!(fs::metadata(Path::new(&resource).join(&name)));
+        // entries are listed only if they could be served, which leaves out
+        // the symbolic links the policy refuses or leading out of the root
+        let entries = entries.into_iter()
+            .filter(|entry| {
+                let path = resource.join(&entry.file_name);
+                self.is_visible(&path) && resolve::verify(&self.root, &path, self.symlinks).is_ok()
+            })
+            .collect();
 
-                if metadata.is_dir() {
-                    name = format!("{}/", name);
-                }
+        let format = listing_format(req);
+        let view = View::from_query(req.query().as_ref(), format);
+        let page = view.apply(entries);
 
-                let mut path = req.path().to_owned();
-                path.push_str(&name);
-                let path = perc_enc::percent_encode(
-                    path.as_bytes(),
-                    perc_enc::DEFAULT_ENCODE_SET
-                );
+        res.with_header("Content-Type", &format.content_type())
+            .with_header("Vary", "Accept");
+        self.add_cache_headers(res, &resource, format.media_type());
 
-                try!(res.write(format!("<li><a href=\"{0}\">{1}</a></li>", path, name).as_bytes()));
-            }
-            try!(res.write("</ul></body></html>".as_bytes()));
-            try!(res.flush());
+        res.start(|res| listing::write(res, format, &req.raw_path_components(), &view, &page))
+    }
+
+    /// Stream the tree under `resource` as an archive, leaving out the
+    /// hidden files and whatever the symbolic link policy refuses
+    ///
+    /// The size is not known before the archive is written, so it is sent
+    /// with chunked transfer-encoding
+    fn send_archive(&self, req: &Request, res: &mut Response, resource: &Path, kind: Kind) -> Result<(), io::Error> {
+        let name = resource.strip_prefix(&self.root).ok()
+            .and_then(|relative| relative.file_name())
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or("archive".to_owned());
+
+        res.with_header("Content-Type", kind.content_type())
+            .with_header("Content-Disposition", &disposition::attachment(&format!("{}.{}", name, kind.extension())));
+
+        if req.method() == "HEAD" {
+            return res.start(|_| Ok(()));
+        }
 
+        res.start(|res| {
+            try!(archive::write(&mut *res, kind, resource, &name, |path| {
+                self.is_visible(path) && resolve::verify(&self.root, path, self.symlinks).is_ok()
+            }));
+            try!(res.flush());
             Ok(())
         })
     }
 }
+
+impl Handler for ServerHandler<DirectoryMode> {
+    fn handle_request(&self, req: &mut Request, res: &mut Response) -> Result<(), io::Error> {
+        self.dispatch(req, res, ServerHandler::<DirectoryMode>::serve)
+    }
+}
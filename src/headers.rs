@@ -1,22 +1,33 @@
 use std::collections::HashMap;
 
-#[derive(Clone, Debug)]
+/// Header fields with their values
+///
+/// Field names are case-insensitive, so fields are looked up by their
+/// lowercased name while keeping the spelling they were first given with
+#[derive(Clone, Debug, Default)]
 pub struct Headers {
-    data: HashMap<String, Vec<String>>,
+    data: HashMap<String, (String, Vec<String>)>,
 }
 
 #[allow(dead_code)]
 impl Headers {
     pub fn new() -> Self {
         Headers {
-            data: HashMap::<String, Vec<String>>::new(),
+            data: HashMap::<String, (String, Vec<String>)>::new(),
         }
     }
 
     pub fn with_data(data: HashMap<String, Vec<String>>) -> Self {
-        Headers {
-            data: data,
+        let mut headers = Headers::new();
+        for (name, values) in data {
+            headers.entry(&name).extend(values);
         }
+        headers
+    }
+
+    fn entry(&mut self, name: &str) -> &mut Vec<String> {
+        let entry = self.data.entry(name.to_lowercase()).or_insert((name.to_owned(), Vec::<String>::new()));
+        &mut entry.1
     }
 
     pub fn parse(&mut self, header: &str) -> &Self {
@@ -24,21 +35,19 @@ impl Headers {
         let name = header[0];
 
         for value in header[1].split(',') {
-            let mut vec = self.data.entry(name.trim().to_owned()).or_insert(Vec::<String>::new());
-            vec.push(value.trim().to_owned());
+            self.entry(name.trim()).push(value.trim().to_owned());
         }
 
         self
     }
 
     pub fn insert(&mut self, name: &str, value: &str) {
-        let mut vec = self.data.entry(name.to_owned()).or_insert(Vec::<String>::new());
-        vec.push(value.to_owned());
+        self.entry(name).push(value.to_owned());
     }
 
     pub fn find(&self, key: &str) -> Option<Vec<&str>> {
-        match self.data.get(key) {
-            Some(vec) => {
+        match self.data.get(&key.to_lowercase()) {
+            Some(&(_, ref vec)) => {
                 if vec.is_empty() {
                     None
                 } else {
@@ -50,12 +59,24 @@ impl Headers {
         }
     }
 
+    /// Values of a header joined back into a single string
+    ///
+    /// Values are split on commas when parsed, which this undoes for the
+    /// headers where a comma is not a separator, like dates
+    pub fn value(&self, key: &str) -> Option<String> {
+        self.find(key).map(|values| values.join(", "))
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        self.data.remove(&key.to_lowercase());
+    }
+
     pub fn has(&self, key: &str) -> bool {
-        self.data.contains_key(key)
+        self.data.contains_key(&key.to_lowercase())
     }
 
     pub fn all(&self) -> Vec<(&str, Vec<&str>)> {
-        let vec = self.data.iter().map(|(key, values)| {
+        let vec = self.data.values().map(|&(ref key, ref values)| {
             let header_vec: Vec<&str> = values.iter().map(|x| x.as_ref()).collect();
             (key.as_ref(), header_vec)
         }).collect();
@@ -68,7 +89,7 @@ impl ToString for Headers {
     fn to_string(&self) -> String {
         let mut result = String::new();
 
-        for (key, vec) in &self.data {
+        for &(ref key, ref vec) in self.data.values() {
             let mut iter = vec.iter();
             match iter.next() {
                 Some(i) => result.push_str(&format!("{}: {}", key, i)),
@@ -3,15 +3,29 @@ extern crate time;
 extern crate threadpool;
 extern crate url;
 extern crate conduit_mime_types;
+extern crate flate2;
+extern crate libc;
 
 pub use server::HttpServer;
 pub use request::Request;
 pub use response::Response;
 
+pub mod access;
+pub mod archive;
+pub mod cache;
+pub mod cache_control;
+pub mod client;
+pub mod compress;
+pub mod conditional;
+pub mod disposition;
 pub mod handler;
 pub mod headers;
+pub mod listing;
+pub mod negotiate;
 pub mod parser;
 pub mod query;
+pub mod range;
 pub mod request;
+pub mod resolve;
 pub mod response;
 pub mod server;
@@ -0,0 +1,533 @@
+use std::cmp::Ordering;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use time::{self, Timespec};
+use url::percent_encoding::{self, FORM_URLENCODED_ENCODE_SET};
+
+use ::archive;
+use ::conditional;
+use ::query::Query;
+
+/// Entries per page of an HTML listing when no page size is asked for
+const DEFAULT_HTML_PAGE_SIZE: usize = 500;
+
+/// Representations a directory listing can be sent as
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Html,
+    Json,
+    Text,
+    Xml,
+}
+
+impl Format {
+    /// All the formats, the first one being the default
+    pub fn all() -> Vec<Format> {
+        vec![Format::Html, Format::Json, Format::Text, Format::Xml]
+    }
+
+    /// Format for the value of a `?format=` parameter
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name {
+            "html" => Some(Format::Html),
+            "json" => Some(Format::Json),
+            "text" | "txt" => Some(Format::Text),
+            "xml" => Some(Format::Xml),
+            _ => None,
+        }
+    }
+
+    pub fn media_type(&self) -> &'static str {
+        match *self {
+            Format::Html => "text/html",
+            Format::Json => "application/json",
+            Format::Text => "text/plain",
+            Format::Xml => "application/xml",
+        }
+    }
+
+    pub fn content_type(&self) -> String {
+        format!("{}; charset=utf-8", self.media_type())
+    }
+}
+
+/// A file or directory shown in a directory listing
+#[derive(Clone, Debug)]
+pub struct Entry {
+    /// Name for display, names that are not valid UTF-8 being converted lossily
+    pub name: String,
+    /// Exact name of the file, to find it again
+    pub file_name: OsString,
+    /// Percent-encoded name for links, keeping the exact bytes of the name
+    pub encoded_name: String,
+    pub is_dir: bool,
+    pub size: u64,
+    pub modified: Option<i64>,
+}
+
+#[cfg(unix)]
+fn name_bytes(name: &OsStr) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    name.as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+fn name_bytes(name: &OsStr) -> Vec<u8> {
+    name.to_string_lossy().into_owned().into_bytes()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SortKey {
+    Name,
+    Size,
+    Modified,
+}
+
+impl SortKey {
+    fn name(&self) -> &'static str {
+        match *self {
+            SortKey::Name => "name",
+            SortKey::Size => "size",
+            SortKey::Modified => "mtime",
+        }
+    }
+}
+
+/// How the entries of a listing are sorted, filtered and split into pages
+#[derive(Clone, Debug)]
+pub struct View {
+    pub sort: SortKey,
+    pub descending: bool,
+    /// Glob pattern when it holds `*` or `?`, otherwise a substring to look for
+    pub filter: Option<String>,
+    /// Page to show, counting from 1
+    pub page: usize,
+    pub page_size: Option<usize>,
+}
+
+/// The entries of a listing that are shown
+#[derive(Clone, Debug)]
+pub struct Page {
+    pub entries: Vec<Entry>,
+    pub number: usize,
+    pub count: usize,
+    /// Number of entries left by the filter, over all the pages
+    pub total: usize,
+}
+
+fn first_value(query: Option<&Query>, name: &str) -> Option<String> {
+    query.and_then(|query| query.get(name)).map(|values| values[0].clone())
+}
+
+impl View {
+    /// Read the view from the `sort`, `order`, `filter`, `page` and
+    /// `per_page` query parameters
+    ///
+    /// Only HTML listings are paginated unless a page is asked for, so
+    /// tools reading the other formats get every entry
+    pub fn from_query(query: Option<&Query>, format: Format) -> View {
+        let sort = match first_value(query, "sort").as_ref().map(|s| s.as_ref()) {
+            Some("size") => SortKey::Size,
+            Some("mtime") => SortKey::Modified,
+            _ => SortKey::Name,
+        };
+
+        let descending = first_value(query, "order").map(|o| o == "desc").unwrap_or(false);
+        let filter = first_value(query, "filter").and_then(|f| if f.is_empty() { None } else { Some(f) });
+
+        let page = first_value(query, "page").and_then(|p| p.parse().ok()).unwrap_or(0);
+        let page_size = first_value(query, "per_page").and_then(|p| p.parse().ok());
+
+        let page_size = match page_size {
+            Some(0) => None,
+            Some(size) => Some(size),
+            None if format == Format::Html || page > 0 => Some(DEFAULT_HTML_PAGE_SIZE),
+            None => None,
+        };
+
+        View {
+            sort: sort,
+            descending: descending,
+            filter: filter,
+            page: if page > 0 { page } else { 1 },
+            page_size: page_size,
+        }
+    }
+
+    fn matches(&self, entry: &Entry) -> bool {
+        let filter = match self.filter {
+            Some(ref filter) => filter.to_lowercase(),
+            None => return true,
+        };
+        let name = entry.name.to_lowercase();
+
+        if filter.contains('*') || filter.contains('?') {
+            glob_matches(&filter, &name)
+        } else {
+            name.contains(&filter)
+        }
+    }
+
+    /// Filter, sort and cut the entries down to the page of this view,
+    /// directories always coming before files
+    pub fn apply(&self, entries: Vec<Entry>) -> Page {
+        let mut entries: Vec<Entry> = entries.into_iter().filter(|e| self.matches(e)).collect();
+
+        entries.sort_by(|a, b| {
+            let order = match self.sort {
+                SortKey::Name => Ordering::Equal,
+                SortKey::Size => a.size.cmp(&b.size),
+                SortKey::Modified => a.modified.cmp(&b.modified),
+            };
+            let order = if order == Ordering::Equal { natural_cmp(&a.name, &b.name) } else { order };
+            let order = if self.descending { order.reverse() } else { order };
+
+            b.is_dir.cmp(&a.is_dir).then(order)
+        });
+
+        let total = entries.len();
+        let (number, count, entries) = match self.page_size {
+            Some(size) => {
+                let count = if total == 0 { 1 } else { total / size + if total % size > 0 { 1 } else { 0 } };
+                let number = if self.page < count { self.page } else { count };
+                let shown = entries.into_iter().skip((number - 1) * size).take(size).collect();
+                (number, count, shown)
+            },
+            None => (1, 1, entries),
+        };
+
+        Page {
+            entries: entries,
+            number: number,
+            count: count,
+            total: total,
+        }
+    }
+
+    /// Query string leading to this view, with a different page or sorting
+    fn query_string(&self, page: usize, sort: SortKey, descending: bool) -> String {
+        let mut query = format!("?sort={}&order={}", sort.name(), if descending { "desc" } else { "asc" });
+
+        if let Some(ref filter) = self.filter {
+            query.push_str(&format!("&filter={}", encode_segment(filter.as_bytes())));
+        }
+        if let Some(size) = self.page_size {
+            query.push_str(&format!("&page={}&per_page={}", page, size));
+        }
+
+        query
+    }
+}
+
+/// Compare names the way people do, with runs of digits compared by value
+/// so `file2` comes before `file10`
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a_chars = a.chars().peekable();
+    let mut b_chars = b.chars().peekable();
+
+    loop {
+        let (a_digit, b_digit) = match (a_chars.peek(), b_chars.peek()) {
+            (None, None) => break,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(a), Some(b)) => (a.is_digit(10), b.is_digit(10)),
+        };
+
+        if a_digit && b_digit {
+            let mut a_number = String::new();
+            while let Some(&c) = a_chars.peek() {
+                if !c.is_digit(10) { break; }
+                a_number.push(c);
+                a_chars.next();
+            }
+
+            let mut b_number = String::new();
+            while let Some(&c) = b_chars.peek() {
+                if !c.is_digit(10) { break; }
+                b_number.push(c);
+                b_chars.next();
+            }
+
+            // without leading zeros, a longer run is a larger number
+            let a_number = a_number.trim_left_matches('0');
+            let b_number = b_number.trim_left_matches('0');
+            let order = a_number.len().cmp(&b_number.len()).then(a_number.cmp(b_number));
+            if order != Ordering::Equal {
+                return order;
+            }
+            continue;
+        }
+
+        let a_char = a_chars.next().unwrap();
+        let b_char = b_chars.next().unwrap();
+        let order = a_char.to_lowercase().cmp(b_char.to_lowercase());
+        if order != Ordering::Equal {
+            return order;
+        }
+    }
+
+    a.cmp(b)
+}
+
+/// Match a name against a pattern where `*` stands for any run of
+/// characters and `?` for a single one
+pub fn glob_matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    let (mut p, mut n) = (0, 0);
+    // where to resume after the last star, should the rest fail to match
+    let mut star: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Read the entries of a directory, in no particular order
+///
+/// Entries whose metadata cannot be read, like dangling symbolic links,
+/// are left out
+pub fn read_entries(dir: &Path) -> Result<Vec<Entry>, io::Error> {
+    let mut entries = Vec::new();
+
+    for entry in try!(fs::read_dir(dir)) {
+        let entry = try!(entry);
+
+        let metadata = match fs::metadata(entry.path()).or_else(|_| entry.metadata()) {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+
+        let name = entry.file_name();
+        entries.push(Entry {
+            name: name.to_string_lossy().into_owned(),
+            encoded_name: encode_segment(&name_bytes(&name)),
+            file_name: name,
+            is_dir: metadata.is_dir(),
+            size: metadata.len(),
+            modified: conditional::modified_time(&metadata),
+        });
+    }
+
+    Ok(entries)
+}
+
+/// Percent-encode a path segment, slashes included
+pub fn encode_segment(bytes: &[u8]) -> String {
+    percent_encoding::percent_encode(bytes, FORM_URLENCODED_ENCODE_SET)
+}
+
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Link to the directory made of the given percent-encoded components
+pub fn directory_href(components: &[&str]) -> String {
+    let mut href = "/".to_owned();
+    for component in components {
+        href.push_str(component);
+        href.push('/');
+    }
+    href
+}
+
+fn format_time(seconds: i64) -> String {
+    time::strftime("%Y-%m-%d %H:%M", &time::at_utc(Timespec::new(seconds, 0))).unwrap_or(String::new())
+}
+
+fn format_rfc3339(seconds: i64) -> String {
+    time::strftime("%Y-%m-%dT%H:%M:%SZ", &time::at_utc(Timespec::new(seconds, 0))).unwrap_or(String::new())
+}
+
+/// Link to an entry of the directory whose link is `base`
+fn entry_href(base: &str, entry: &Entry) -> String {
+    format!("{}{}{}", base, entry.encoded_name, if entry.is_dir { "/" } else { "" })
+}
+
+fn entry_type(entry: &Entry) -> &'static str {
+    if entry.is_dir { "directory" } else { "file" }
+}
+
+fn escape_json(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Write a page of the listing in the given format
+pub fn write<W: Write>(out: &mut W, format: Format, components: &[&str], view: &View, page: &Page) -> Result<(), io::Error> {
+    match format {
+        Format::Html => write_html(out, components, view, page),
+        Format::Json => write_json(out, components, page),
+        Format::Text => write_text(out, &page.entries),
+        Format::Xml => write_xml(out, components, page),
+    }
+}
+
+/// Write the names of the entries one per line, directories ending with a slash
+pub fn write_text<W: Write>(out: &mut W, entries: &[Entry]) -> Result<(), io::Error> {
+    for entry in entries {
+        try!(write!(out, "{}{}\n", entry.name, if entry.is_dir { "/" } else { "" }));
+    }
+    out.flush()
+}
+
+pub fn write_json<W: Write>(out: &mut W, components: &[&str], page: &Page) -> Result<(), io::Error> {
+    let components: Vec<&str> = components.iter().cloned().filter(|c| !c.is_empty()).collect();
+    let base = directory_href(&components);
+
+    try!(write!(out, "{{\"path\":\"{}\",\"page\":{},\"pages\":{},\"total\":{},\"entries\":[",
+                escape_json(&base), page.number, page.count, page.total));
+
+    for (i, entry) in page.entries.iter().enumerate() {
+        let modified = match entry.modified {
+            Some(modified) => format!("\"{}\"", format_rfc3339(modified)),
+            None => "null".to_owned(),
+        };
+
+        try!(write!(out, "{}{{\"name\":\"{}\",\"type\":\"{}\",\"size\":{},\"mtime\":{},\"href\":\"{}\"}}",
+                    if i > 0 { "," } else { "" },
+                    escape_json(&entry.name), entry_type(entry), entry.size, modified,
+                    escape_json(&entry_href(&base, entry))));
+    }
+
+    try!(write!(out, "]}}\n"));
+    out.flush()
+}
+
+pub fn write_xml<W: Write>(out: &mut W, components: &[&str], page: &Page) -> Result<(), io::Error> {
+    let components: Vec<&str> = components.iter().cloned().filter(|c| !c.is_empty()).collect();
+    let base = directory_href(&components);
+
+    try!(write!(out, "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<listing path=\"{}\" page=\"{}\" pages=\"{}\" total=\"{}\">\n",
+                escape_html(&base), page.number, page.count, page.total));
+
+    for entry in &page.entries {
+        try!(write!(out, "<entry><name>{}</name><type>{}</type><size>{}</size>",
+                    escape_html(&entry.name), entry_type(entry), entry.size));
+        if let Some(modified) = entry.modified {
+            try!(write!(out, "<updated>{}</updated>", format_rfc3339(modified)));
+        }
+        try!(write!(out, "<link href=\"{}\"/></entry>\n", escape_html(&entry_href(&base, entry))));
+    }
+
+    try!(write!(out, "</listing>\n"));
+    out.flush()
+}
+
+/// Write an HTML page of the listing, the directory being given by the
+/// percent-encoded components of its path
+pub fn write_html<W: Write>(out: &mut W, components: &[&str], view: &View, page: &Page) -> Result<(), io::Error> {
+    let components: Vec<&str> = components.iter().cloned().filter(|c| !c.is_empty()).collect();
+    let decoded: Vec<String> = components.iter()
+        .map(|c| percent_encoding::lossy_utf8_percent_decode(c.as_bytes()))
+        .collect();
+
+    let title = escape_html(&format!("Index of /{}", decoded.iter()
+        .fold(String::new(), |path, c| path + c + "/")));
+
+    try!(write!(out, "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{}</title></head><body>\n", title));
+
+    // breadcrumbs, each leading to one of the parent directories
+    try!(write!(out, "<h1>Index of <a href=\"/\">/</a>"));
+    for (i, name) in decoded.iter().enumerate() {
+        let href = escape_html(&directory_href(&components[..i + 1]));
+        try!(write!(out, "<a href=\"{}\">{}</a>/", href, escape_html(name)));
+    }
+    try!(write!(out, "</h1>\n<table>\n<tr>"));
+
+    // column headings sort by their column, a second click reversing the order
+    for &(key, heading) in &[(SortKey::Name, "Name"), (SortKey::Size, "Size"), (SortKey::Modified, "Modified")] {
+        let descending = view.sort == key && !view.descending;
+        try!(write!(out, "<th><a href=\"{}\">{}</a></th>", escape_html(&view.query_string(1, key, descending)), heading));
+    }
+    try!(write!(out, "</tr>\n"));
+
+    let base = directory_href(&components);
+
+    if !components.is_empty() {
+        let parent = escape_html(&directory_href(&components[..components.len() - 1]));
+        try!(write!(out, "<tr><td><a href=\"{}\">../</a></td><td>-</td><td></td></tr>\n", parent));
+    }
+
+    for entry in &page.entries {
+        let (slash, size) = if entry.is_dir {
+            ("/", "-".to_owned())
+        } else {
+            ("", entry.size.to_string())
+        };
+        let modified = entry.modified.map(format_time).unwrap_or(String::new());
+
+        try!(write!(out, "<tr><td><a href=\"{}\">{}{}</a></td><td>{}</td><td>{}</td></tr>\n",
+                    escape_html(&entry_href(&base, entry)), escape_html(&entry.name), slash, size, modified));
+    }
+
+    try!(write!(out, "</table>\n"));
+
+    if page.count > 1 {
+        try!(write!(out, "<p>"));
+        if page.number > 1 {
+            let href = view.query_string(page.number - 1, view.sort, view.descending);
+            try!(write!(out, "<a href=\"{}\" rel=\"prev\">Previous</a> ", escape_html(&href)));
+        }
+        try!(write!(out, "Page {} of {}", page.number, page.count));
+        if page.number < page.count {
+            let href = view.query_string(page.number + 1, view.sort, view.descending);
+            try!(write!(out, " <a href=\"{}\" rel=\"next\">Next</a>", escape_html(&href)));
+        }
+        try!(write!(out, "</p>\n"));
+    }
+
+    // the whole tree under the directory, in each archive format
+    try!(write!(out, "<p>Download as"));
+    for kind in &archive::Kind::all() {
+        try!(write!(out, " <a href=\"?archive={0}\" download>{0}</a>", kind.extension()));
+    }
+    try!(write!(out, "</p>\n"));
+
+    try!(write!(out, "</body></html>\n"));
+    out.flush()
+}
@@ -2,36 +2,193 @@ extern crate mahardhika;
 extern crate argparse;
 
 use std::env;
-use argparse::{ArgumentParser, Store, StoreTrue};
+use std::process;
+use std::sync::Arc;
+use std::time::Duration;
+use argparse::{ArgumentParser, Collect, Store, StoreFalse, StoreTrue};
 
 use mahardhika::HttpServer;
+use mahardhika::access::AccessPolicy;
+use mahardhika::cache::FileCache;
+use mahardhika::cache_control::{CacheRules, Directive, Matcher};
+use mahardhika::compress::Compression;
 use mahardhika::handler::{Handler, ServerHandler, FileMode, DirectoryMode};
+use mahardhika::resolve::SymlinkPolicy;
 
 const DEFAULT_ADDR: &'static str = "127.0.0.1:8000";
 
+/// Largest file kept in the cache
+const MAX_CACHED_FILE_SIZE: u64 = 1024 * 1024;
+
 fn main() {
     let mut addr = DEFAULT_ADDR.to_owned();
     let mut dir_mode = false;
+    let mut threads = 4usize;
+    let mut max_requests = 100usize;
+    let mut max_body_size = 1024 * 1024u64;
+    let mut timeout = 5u64;
+    let mut symlinks = "follow".to_owned();
+    let mut index = "index.html".to_owned();
+    let mut compress = true;
+    let mut cache_size = 0u64;
+    let mut cache_rules: Vec<String> = Vec::new();
+    let mut cache_default = String::new();
+    let mut show_hidden = false;
+    let mut deny: Vec<String> = Vec::new();
+    let mut allow: Vec<String> = Vec::new();
+    let mut error_pages = String::new();
+    let mut spa = String::new();
+    let mut clean_urls = String::new();
+    let mut redirect_clean_urls = false;
+    let mut downloads: Vec<String> = Vec::new();
 
     {
         let mut parser = ArgumentParser::new();
         parser.set_description("mahardhika http server");
         parser.refer(&mut addr).add_option(&["-a", "--addr"], Store, "Address to listen");
         parser.refer(&mut dir_mode).add_option(&["-d", "--dir"], StoreTrue, "Enable directory listing within root");
+        parser.refer(&mut threads).add_option(&["-t", "--threads"], Store, "Number of threads serving connections, one per open connection");
+        parser.refer(&mut max_requests).add_option(&["--max-requests"], Store, "Maximum requests served per connection");
+        parser.refer(&mut max_body_size).add_option(&["--max-body-size"], Store, "Largest request body accepted, in bytes");
+        parser.refer(&mut timeout).add_option(&["--keep-alive-timeout"], Store, "Seconds an idle connection is kept open (0 to wait forever)");
+        parser.refer(&mut symlinks).add_option(&["--symlinks"], Store, "Symbolic links to follow: follow, owner or never");
+        parser.refer(&mut index).add_option(&["--index"], Store, "Comma-separated index files served for directories (empty for none)");
+        parser.refer(&mut compress).add_option(&["--no-compress"], StoreFalse, "Send responses without compressing them on the fly");
+        parser.refer(&mut cache_size).add_option(&["--cache-size"], Store, "Bytes of small files kept in memory (0 to disable)");
+        parser.refer(&mut cache_rules).add_option(&["--cache-rule"], Collect, "Cache-Control directives for a path glob or media type, as PATTERN=DIRECTIVES");
+        parser.refer(&mut cache_default).add_option(&["--cache-default"], Store, "Cache-Control directives for responses no rule matches");
+        parser.refer(&mut show_hidden).add_option(&["--show-hidden"], StoreTrue, "Serve and list dotfiles");
+        parser.refer(&mut deny).add_option(&["--deny"], Collect, "Glob of files to hide, matched against names or against paths when holding a slash");
+        parser.refer(&mut allow).add_option(&["--allow"], Collect, "Glob of files to serve even when hidden otherwise, matched against names like .well-known or against paths from the root when holding a slash like /.well-known/*");
+        parser.refer(&mut error_pages).add_option(&["--error-pages"], Store, "Directory of error documents like 404.html, relative to the root");
+        parser.refer(&mut spa).add_option(&["--spa"], Store, "Document served for client-side routes of a single-page application, relative to the root");
+        parser.refer(&mut clean_urls).add_option(&["--clean-urls"], Store, "Comma-separated extensions tried for paths missing one, like html");
+        parser.refer(&mut redirect_clean_urls).add_option(&["--redirect-clean-urls"], StoreTrue, "Redirect paths ending with one of those extensions to the clean URL");
+        parser.refer(&mut downloads).add_option(&["--download"], Collect, "Glob of files sent as attachments to save rather than display");
         parser.parse_args_or_exit();
     }
 
+    if threads == 0 {
+        println!("At least one thread is needed to serve connections");
+        process::exit(2);
+    }
+
+    let symlinks = match symlinks.as_ref() {
+        "follow" => SymlinkPolicy::Follow,
+        "owner" => SymlinkPolicy::FollowIfOwnerMatches,
+        "never" => SymlinkPolicy::Never,
+        other => {
+            println!("Unknown symbolic link policy '{}'", other);
+            process::exit(2);
+        }
+    };
+
+    let mut rules = CacheRules::new();
+    for rule in &cache_rules {
+        let mut parts = rule.splitn(2, '=');
+        let (pattern, directives) = (parts.next().unwrap_or(""), parts.next());
+
+        match directives.and_then(Directive::parse_list) {
+            Some(directives) if !pattern.is_empty() => {
+                rules.with_rule(Matcher::parse(pattern), directives);
+            },
+            _ => {
+                println!("Invalid cache rule '{}'", rule);
+                process::exit(2);
+            }
+        }
+    }
+    match Directive::parse_list(&cache_default) {
+        Some(directives) => {
+            rules.with_default(directives);
+        },
+        None => {
+            println!("Invalid cache directives '{}'", cache_default);
+            process::exit(2);
+        }
+    }
+
+    let mut access = AccessPolicy::new();
+    access.with_hidden_dotfiles(!show_hidden);
+    for pattern in &deny {
+        access.with_deny(pattern);
+    }
+    for pattern in &allow {
+        access.with_allow(pattern);
+    }
+
+    let clean_extensions: Vec<&str> = clean_urls.split(',').map(|e| e.trim()).filter(|e| !e.is_empty()).collect();
+
+    let index_files: Vec<&str> = index.split(',').map(|name| name.trim()).filter(|name| !name.is_empty()).collect();
+
     // Edit here to change the server root
     let path = env::home_dir().unwrap();
 
-    let handler: Box<Handler + Send + Sync>;
+    let cache = if cache_size > 0 {
+        Some(Arc::new(FileCache::new(cache_size, MAX_CACHED_FILE_SIZE)))
+    } else {
+        None
+    };
+
+    let mut handler: Box<Handler + Send + Sync>;
 
     if dir_mode {
-        handler = Box::new(ServerHandler::<DirectoryMode>::new(&path));
+        let mut dir_handler = ServerHandler::<DirectoryMode>::new(&path);
+        dir_handler.with_symlink_policy(symlinks)
+            .with_index_files(&index_files)
+            .with_cache_rules(rules)
+            .with_access_policy(access)
+            .with_clean_urls(&clean_extensions)
+            .with_clean_url_redirect(redirect_clean_urls);
+        if let Some(ref cache) = cache {
+            dir_handler.with_cache(cache.clone());
+        }
+        if !error_pages.is_empty() {
+            dir_handler.with_error_pages_dir(&path.join(&error_pages));
+        }
+        if !spa.is_empty() {
+            dir_handler.with_spa_fallback(&path.join(&spa));
+        }
+        for pattern in &downloads {
+            dir_handler.with_download(pattern);
+        }
+        handler = Box::new(dir_handler);
+    } else {
+        let mut file_handler = ServerHandler::<FileMode>::new(&path);
+        file_handler.with_symlink_policy(symlinks)
+            .with_index_files(&index_files)
+            .with_cache_rules(rules)
+            .with_access_policy(access)
+            .with_clean_urls(&clean_extensions)
+            .with_clean_url_redirect(redirect_clean_urls);
+        if let Some(ref cache) = cache {
+            file_handler.with_cache(cache.clone());
+        }
+        if !error_pages.is_empty() {
+            file_handler.with_error_pages_dir(&path.join(&error_pages));
+        }
+        if !spa.is_empty() {
+            file_handler.with_spa_fallback(&path.join(&spa));
+        }
+        for pattern in &downloads {
+            file_handler.with_download(pattern);
+        }
+        handler = Box::new(file_handler);
+    }
+
+    if compress {
+        handler = Box::new(Compression::new(handler));
+    }
+
+    let mut server: HttpServer = HttpServer::new(&addr, threads);
+    server.with_max_requests(max_requests)
+        .with_max_body_size(max_body_size);
+
+    if timeout > 0 {
+        server.with_keep_alive_timeout(Some(Duration::from_secs(timeout)));
     } else {
-        handler = Box::new(ServerHandler::<FileMode>::new(&path));
+        server.with_keep_alive_timeout(None);
     }
 
-    let server: HttpServer = HttpServer::new(&addr, 4usize);
     server.start(handler);
 }
@@ -0,0 +1,165 @@
+/// A media range of an `Accept` header with its quality value
+#[derive(Debug, PartialEq)]
+struct MediaRange<'a> {
+    kind: &'a str,
+    subtype: &'a str,
+    quality: f32,
+}
+
+impl<'a> MediaRange<'a> {
+    /// How precisely the range names a media type, when it matches it at all
+    fn specificity(&self, kind: &str, subtype: &str) -> Option<u8> {
+        if self.kind == "*" {
+            Some(0)
+        } else if !self.kind.eq_ignore_ascii_case(kind) {
+            None
+        } else if self.subtype == "*" {
+            Some(1)
+        } else if self.subtype.eq_ignore_ascii_case(subtype) {
+            Some(2)
+        } else {
+            None
+        }
+    }
+}
+
+fn parse_accept<'a>(accept: &'a str) -> Vec<MediaRange<'a>> {
+    let mut ranges = Vec::new();
+
+    for item in accept.split(',') {
+        let mut params = item.split(';').map(|p| p.trim());
+
+        let media_type = params.next().unwrap_or("");
+        let mut parts = media_type.splitn(2, '/');
+        let (kind, subtype) = match (parts.next(), parts.next()) {
+            (Some(kind), Some(subtype)) if !kind.is_empty() && !subtype.is_empty() => (kind, subtype),
+            _ => continue,
+        };
+
+        let mut quality = 1.0;
+        for param in params {
+            if param.starts_with("q=") || param.starts_with("Q=") {
+                quality = param[2..].trim().parse().unwrap_or(0.0);
+            }
+        }
+
+        ranges.push(MediaRange { kind: kind, subtype: subtype, quality: quality });
+    }
+
+    ranges
+}
+
+/// Pick the media type the client prefers among `available`, returning its index
+///
+/// Without an `Accept` header the first type is chosen, and `None` means
+/// none of the types is acceptable. Ties go to the type listed first
+pub fn best_match(accept: Option<&str>, available: &[&str]) -> Option<usize> {
+    let accept = match accept {
+        Some(accept) if !accept.trim().is_empty() => accept,
+        _ => return if available.is_empty() { None } else { Some(0) },
+    };
+
+    let ranges = parse_accept(accept);
+    let mut best: Option<(usize, f32)> = None;
+
+    for (i, media_type) in available.iter().enumerate() {
+        let mut parts = media_type.splitn(2, '/');
+        let (kind, subtype) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+
+        // the most specific range that matches decides the quality
+        let quality = ranges.iter()
+            .filter_map(|range| range.specificity(kind, subtype).map(|s| (s, range.quality)))
+            .fold(None, |found: Option<(u8, f32)>, (s, q)| match found {
+                Some((best_s, _)) if best_s >= s => found,
+                _ => Some((s, q)),
+            })
+            .map(|(_, q)| q)
+            .unwrap_or(0.0);
+
+        if quality <= 0.0 {
+            continue;
+        }
+
+        match best {
+            Some((_, best_quality)) if best_quality >= quality => {},
+            _ => best = Some((i, quality)),
+        }
+    }
+
+    best.map(|(i, _)| i)
+}
+
+/// Whether the `Accept` header names `media_type` itself with a non-zero
+/// quality, as browsers do for navigations, rather than through a wildcard
+pub fn names(accept: Option<&str>, media_type: &str) -> bool {
+    let accept = match accept {
+        Some(accept) => accept,
+        None => return false,
+    };
+
+    let mut parts = media_type.splitn(2, '/');
+    let (kind, subtype) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+
+    parse_accept(accept).iter()
+        .any(|range| range.specificity(kind, subtype) == Some(2) && range.quality > 0.0)
+}
+
+/// Pick the content coding the client prefers among `available`, returning
+/// its index
+///
+/// `None` means the unencoded representation is preferred, which is always
+/// the case without an `Accept-Encoding` header. Ties go to the coding listed
+/// first, and a coding is preferred over identity of the same quality
+pub fn best_encoding(accept_encoding: Option<&str>, available: &[&str]) -> Option<usize> {
+    let accept_encoding = match accept_encoding {
+        Some(accept_encoding) => accept_encoding,
+        None => return None,
+    };
+
+    let mut codings = Vec::new();
+    for item in accept_encoding.split(',') {
+        let mut params = item.split(';').map(|p| p.trim());
+
+        let coding = match params.next() {
+            Some(coding) if !coding.is_empty() => coding.to_lowercase(),
+            _ => continue,
+        };
+        // x-gzip is an alias from the days of HTTP/1.0
+        let coding = if coding == "x-gzip" { "gzip".to_owned() } else { coding };
+
+        let mut quality = 1.0;
+        for param in params {
+            if param.starts_with("q=") || param.starts_with("Q=") {
+                quality = param[2..].trim().parse().unwrap_or(0.0);
+            }
+        }
+
+        codings.push((coding, quality));
+    }
+
+    let quality_of = |name: &str| -> Option<f32> {
+        codings.iter().find(|&&(ref coding, _)| coding == name).map(|&(_, q)| q)
+    };
+    let wildcard = quality_of("*");
+
+    // identity stays acceptable unless it is refused by name or through `*`
+    let identity = quality_of("identity").or(wildcard.and_then(|q| if q <= 0.0 { Some(q) } else { None })).unwrap_or(1.0);
+
+    let mut best: Option<(usize, f32)> = None;
+    for (i, coding) in available.iter().enumerate() {
+        let quality = quality_of(coding).or(wildcard).unwrap_or(0.0);
+        if quality <= 0.0 {
+            continue;
+        }
+
+        match best {
+            Some((_, best_quality)) if best_quality >= quality => {},
+            _ => best = Some((i, quality)),
+        }
+    }
+
+    match best {
+        Some((i, quality)) if quality >= identity => Some(i),
+        _ => None,
+    }
+}
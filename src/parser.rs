@@ -1,14 +1,22 @@
 use std::error::Error;
-use std::io::{self, Read, BufRead, BufReader, ErrorKind};
+use std::io::BufRead;
 use std::fmt;
-use regex::Regex;
-use url::percent_encoding;
+use std::mem;
+
+/// Longest start line, header line or chunk size line accepted
+const MAX_LINE_LENGTH: usize = 8192;
 
 #[derive(Debug)]
 pub struct ParseError {
     description: String,
 }
 
+impl ParseError {
+    pub fn new(description: &str) -> ParseError {
+        ParseError { description: description.to_owned() }
+    }
+}
+
 impl fmt::Display for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", self.description)
@@ -27,6 +35,7 @@ pub trait ParserHandler {
     fn on_query(&mut self, _query: &str) -> Result<(), ParseError> { Ok(()) }
     fn on_http_version(&mut self, _version: &str) -> Result<(), ParseError> { Ok(()) }
     fn on_status(&mut self, _status: u16) -> Result<(), ParseError> { Ok(()) }
+    fn on_reason(&mut self, _reason: &str) -> Result<(), ParseError> { Ok(()) }
     fn on_header(&mut self, _field: &str, _values: Vec<&str>) -> Result<(), ParseError> { Ok(()) }
     fn on_body(&mut self, _part: &[u8]) -> Result<(), ParseError> { Ok(()) }
     fn on_headers_complete(&mut self) -> Result<(), ParseError> { Ok(()) }
@@ -34,87 +43,676 @@ pub trait ParserHandler {
     fn on_message_complete(&mut self) -> Result<(), ParseError> { Ok(()) }
 }
 
+/// Outcome of feeding bytes to the parser
+#[derive(Debug, PartialEq)]
+pub enum Progress {
+    /// All the bytes were consumed and the message is not finished yet
+    Incomplete,
+    /// The message ended after the given number of bytes, the remaining
+    /// ones belong to the next message
+    Complete(usize),
+}
+
+/// How the end of a message body is found
+enum BodyFraming {
+    None,
+    Length(u64),
+    Chunked,
+    /// Only responses can be delimited by closing the connection
+    UntilClose,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Request,
+    Response,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    /// Waiting for the start line, empty lines before it are skipped
+    Start,
+    Headers,
+    Body(u64),
+    BodyUntilClose,
+    ChunkSize,
+    ChunkData(u64),
+    ChunkDataEnd,
+    Trailers,
+}
+
+/// Resumable HTTP parser
+///
+/// Bytes are pushed in with `feed` in fragments of any size, the parser
+/// keeps its position between calls and reports what it finds through
+/// the `ParserHandler` callbacks
 pub struct Parser<'a, H: 'a> {
     handler: &'a mut H,
+    kind: Kind,
+    state: State,
+    line: Vec<u8>,
+    framing: BodyFraming,
+    status: u16,
+    head_request: bool,
 }
 
 impl<'a, H: ParserHandler> Parser<'a, H> {
+    fn new(handler: &'a mut H, kind: Kind) -> Parser<'a, H> {
+        Parser {
+            handler: handler,
+            kind: kind,
+            state: State::Start,
+            line: Vec::new(),
+            framing: BodyFraming::None,
+            status: 0,
+            head_request: false,
+        }
+    }
+
     pub fn request(handler: &'a mut H) -> Parser<'a, H> {
-        Parser { handler: handler }
+        Parser::new(handler, Kind::Request)
     }
 
-    pub fn parse<R: Read>(&mut self, stream: &mut R) -> Result<(), Box<Error>> {
-        let mut buf_reader = BufReader::new(stream);
+    pub fn response(handler: &'a mut H) -> Parser<'a, H> {
+        Parser::new(handler, Kind::Response)
+    }
+
+    /// Tell the parser the responses answer a `HEAD` request, so they have
+    /// no body whatever their headers say
+    pub fn with_head_request(&mut self, head_request: bool) -> &mut Self {
+        self.head_request = head_request;
+        self
+    }
+
+    /// Whether the parser is between messages, with no bytes of the next one seen
+    pub fn is_idle(&self) -> bool {
+        self.state == State::Start && self.line.is_empty()
+    }
+
+    /// Parse a single message from the stream
+    ///
+    /// The reader is borrowed rather than wrapped so that bytes buffered past
+    /// the end of this message are still available for the next call, which is
+    /// what allows several requests to be read from one connection
+    pub fn parse<R: BufRead>(&mut self, stream: &mut R) -> Result<(), Box<Error>> {
+        loop {
+            let (progress, available) = {
+                let data = try!(stream.fill_buf());
+
+                if data.is_empty() {
+                    try!(self.finish());
+                    return Ok(());
+                }
 
-        let mut request_line = String::new();
-        let bytes_read = try!(buf_reader.read_line(&mut request_line));
+                (try!(self.feed(data)), data.len())
+            };
 
-        if bytes_read == 0 || request_line.is_empty() {
+            match progress {
+                Progress::Complete(used) => {
+                    stream.consume(used);
+                    return Ok(());
+                },
+                Progress::Incomplete => stream.consume(available),
+            }
+        }
+    }
+
+    /// Signal the end of the input
+    ///
+    /// This completes a response whose body runs until the connection is
+    /// closed, while for any other unfinished message it is an error
+    pub fn finish(&mut self) -> Result<(), ParseError> {
+        if self.is_idle() {
             return Ok(());
         }
 
-        try!(self.handler.on_message_begin());
+        match self.state {
+            State::BodyUntilClose => self.complete(),
+            _ => Err(ParseError::new("Incomplete Message")),
+        }
+    }
+
+    /// Push the next fragment of input into the parser
+    ///
+    /// Returns as soon as a message is complete, leaving the parser ready
+    /// for the next one
+    pub fn feed(&mut self, data: &[u8]) -> Result<Progress, ParseError> {
+        let mut pos = 0;
+
+        while pos < data.len() {
+            match self.state {
+                State::BodyUntilClose => {
+                    try!(self.handler.on_body(&data[pos..]));
+                    pos = data.len();
+                },
+                State::Body(remaining) | State::ChunkData(remaining) => {
+                    let available = (data.len() - pos) as u64;
+                    let len = if remaining < available { remaining } else { available };
+                    let end = pos + len as usize;
+
+                    try!(self.handler.on_body(&data[pos..end]));
+                    pos = end;
+
+                    let remaining = remaining - len;
+                    self.state = match self.state {
+                        State::Body(_) if remaining == 0 => {
+                            try!(self.complete());
+                            return Ok(Progress::Complete(pos));
+                        },
+                        State::Body(_) => State::Body(remaining),
+                        _ if remaining == 0 => State::ChunkDataEnd,
+                        _ => State::ChunkData(remaining),
+                    };
+                },
+                _ => {
+                    let rest = &data[pos..];
+                    let complete = match rest.iter().position(|&b| b == b'\n') {
+                        Some(i) => {
+                            self.line.extend(rest[..i].iter().cloned());
+                            pos += i + 1;
+                            true
+                        },
+                        None => {
+                            self.line.extend(rest.iter().cloned());
+                            pos = data.len();
+                            false
+                        },
+                    };
+
+                    if self.line.len() > MAX_LINE_LENGTH {
+                        return Err(ParseError::new("Line Too Long"));
+                    }
+
+                    if !complete {
+                        break;
+                    }
+
+                    let mut line = mem::replace(&mut self.line, Vec::new());
+                    if line.last() == Some(&b'\r') {
+                        line.pop();
+                    }
+
+                    if try!(self.parse_line(&String::from_utf8_lossy(&line))) {
+                        return Ok(Progress::Complete(pos));
+                    }
+                },
+            }
+        }
 
-        let re = Regex::new(
-            r"^(?P<method>[A-Z]*?) (?P<url>[^\?]+)(\?(?P<query>[^#]+))? HTTP/(?P<version>\d\.\d)\r\n$"
-        ).unwrap();
+        Ok(Progress::Incomplete)
+    }
 
-        match re.captures(&request_line) {
-            Some(cap) => {
-                let method = cap.name("method").unwrap();
-                try!(self.handler.on_method(method));
+    /// Handle a complete line, returning whether it ended the message
+    fn parse_line(&mut self, line: &str) -> Result<bool, ParseError> {
+        match self.state {
+            State::Start => {
+                if !line.is_empty() {
+                    try!(self.handler.on_message_begin());
+                    match self.kind {
+                        Kind::Request => try!(self.parse_request_line(line)),
+                        Kind::Response => try!(self.parse_status_line(line)),
+                    }
+                    self.framing = BodyFraming::None;
+                    self.state = State::Headers;
+                }
+            },
+            State::Headers => {
+                if !line.is_empty() {
+                    try!(self.parse_header_line(line, true));
+                    return Ok(false);
+                }
 
-                let url = percent_encoding::lossy_utf8_percent_decode(
-                    cap.name("url").unwrap().as_bytes()
-                );
-                try!(self.handler.on_url(&url));
+                try!(self.handler.on_headers_complete());
 
-                match cap.name("query") {
-                    Some(query) => {
-                        let query = percent_encoding::lossy_utf8_percent_decode(query.as_bytes());
-                        try!(self.handler.on_query(&query));
+                if self.kind == Kind::Response {
+                    let status = self.status;
+                    if self.head_request || status / 100 == 1 || status == 204 || status == 304 {
+                        self.framing = BodyFraming::Length(0);
+                    } else if let BodyFraming::None = self.framing {
+                        self.framing = BodyFraming::UntilClose;
                     }
-                    None => {}
                 }
 
-                let version = cap.name("version").unwrap();
-                try!(self.handler.on_http_version(version));
+                self.state = match self.framing {
+                    BodyFraming::None | BodyFraming::Length(0) => {
+                        try!(self.complete());
+                        return Ok(true);
+                    },
+                    BodyFraming::Length(length) => State::Body(length),
+                    BodyFraming::Chunked => State::ChunkSize,
+                    BodyFraming::UntilClose => State::BodyUntilClose,
+                };
+            },
+            State::ChunkSize => {
+                // chunk extensions are ignored
+                let size = line.split(';').next().unwrap().trim();
+                let size = match u64::from_str_radix(size, 16) {
+                    Ok(size) => size,
+                    Err(_) => return Err(ParseError::new(&format!("Invalid Chunk Size: '{}'", size))),
+                };
+
+                self.state = if size == 0 { State::Trailers } else { State::ChunkData(size) };
             },
-            None => {
-                let error = io::Error::new(ErrorKind::InvalidInput, "Malformed Request");
-                return Err(Box::new(error));
+            State::ChunkDataEnd => {
+                if !line.is_empty() {
+                    return Err(ParseError::new("Missing Chunk Terminator"));
+                }
+                self.state = State::ChunkSize;
             },
+            State::Trailers => {
+                // trailers are reported as headers
+                if !line.is_empty() {
+                    try!(self.parse_header_line(line, false));
+                    return Ok(false);
+                }
+
+                try!(self.complete());
+                return Ok(true);
+            },
+            State::Body(_) | State::BodyUntilClose | State::ChunkData(_) => unreachable!(),
+        }
+
+        Ok(false)
+    }
+
+    fn parse_request_line(&mut self, line: &str) -> Result<(), ParseError> {
+        let parts: Vec<&str> = line.split(' ').collect();
+        if parts.len() != 3 {
+            return Err(ParseError::new("Malformed Request"));
+        }
+
+        let method = parts[0];
+        if method.is_empty() || !method.chars().all(|c| c >= 'A' && c <= 'Z') {
+            return Err(ParseError::new("Malformed Request"));
+        }
+
+        // the fragment should never be sent, but is dropped if it is
+        let target = parts[1].splitn(2, '#').next().unwrap();
+        let mut target = target.splitn(2, '?');
+        let url = target.next().unwrap();
+        if url.is_empty() {
+            return Err(ParseError::new("Malformed Request"));
+        }
+
+        let version = match parse_version(parts[2]) {
+            Some(version) => version,
+            None => return Err(ParseError::new("Malformed Request")),
         };
 
-        // reading headers
-        for line in buf_reader.lines() {
-            match line {
-                Ok(header_line) => {
-                    // read an empty line
-                    if header_line.trim().len() == 0 {
-                        break;
+        try!(self.handler.on_method(method));
+
+        // the path is passed on still encoded, so that an encoded slash can
+        // be told apart from a separator when it is split
+        try!(self.handler.on_url(url));
+
+        // so is the query, its pairs being decoded only once they are split
+        match target.next() {
+            Some(query) if !query.is_empty() => try!(self.handler.on_query(query)),
+            _ => {},
+        }
+
+        self.handler.on_http_version(version)
+    }
+
+    fn parse_status_line(&mut self, line: &str) -> Result<(), ParseError> {
+        let mut parts = line.splitn(3, ' ');
+
+        let version = match parts.next().and_then(parse_version) {
+            Some(version) => version,
+            None => return Err(ParseError::new("Malformed Response")),
+        };
+
+        // the status code is always three digits
+        let status = match parts.next() {
+            Some(status) if status.len() == 3 => status.parse().ok(),
+            _ => None,
+        };
+        let status = match status {
+            Some(status) => status,
+            None => return Err(ParseError::new("Malformed Response")),
+        };
+        self.status = status;
+
+        try!(self.handler.on_http_version(version));
+        try!(self.handler.on_status(status));
+
+        // the reason phrase may be empty or missing altogether
+        self.handler.on_reason(parts.next().unwrap_or(""))
+    }
+
+    /// Parse a header line, working out the body framing from it when
+    /// `framing` is set
+    fn parse_header_line(&mut self, line: &str, framing: bool) -> Result<(), ParseError> {
+        let colon = match line.find(':') {
+            Some(colon) => colon,
+            None => return Err(ParseError::new(&format!("Invalid Header: '{}'", line))),
+        };
+
+        let field = &line[..colon];
+        let value = line[colon + 1..].trim();
+
+        if field.is_empty() || field.trim() != field {
+            return Err(ParseError::new(&format!("Invalid Header: '{}'", line)));
+        }
+
+        let values: Vec<&str> = value.split(',').map(|h| h.trim()).collect();
+
+        if framing {
+            match field.to_lowercase().as_ref() {
+                "transfer-encoding" => {
+                    // a request body must end with chunked, a response may
+                    // otherwise run until the connection is closed
+                    if values.last().map(|v| v.to_lowercase()) == Some("chunked".to_owned()) {
+                        self.framing = BodyFraming::Chunked;
+                    } else if self.kind == Kind::Response {
+                        self.framing = BodyFraming::UntilClose;
+                    } else {
+                        return Err(ParseError::new(&format!("Unsupported Transfer-Encoding: '{}'", value)));
                     }
-                    let header: Vec<_> = header_line.split(": ").collect();
-
-                    if header.len() != 2 {
-                        let error = io::Error::new(
-                            ErrorKind::InvalidInput,
-                            format!("Invalid Header: '{}'", header_line),
-                        );
-                        return Err(Box::new(error));
+                },
+                "content-length" => {
+                    let length = match value.parse() {
+                        Ok(length) => length,
+                        Err(_) => return Err(ParseError::new(&format!("Invalid Content-Length: '{}'", value))),
+                    };
+
+                    // Transfer-Encoding takes precedence over Content-Length
+                    match self.framing {
+                        BodyFraming::Chunked | BodyFraming::UntilClose => {},
+                        _ => self.framing = BodyFraming::Length(length),
                     }
+                },
+                _ => {},
+            }
+        }
 
-                    let field = header[0];
-                    let values = header[1].split(',').map(|h| h.trim()).collect();
+        self.handler.on_header(field, values)
+    }
 
-                    try!(self.handler.on_header(field, values));
-                },
-                Err(e) => {
-                    return Err(Box::new(e));
-                }
+    /// Finish the current message and get ready for the next one
+    fn complete(&mut self) -> Result<(), ParseError> {
+        self.state = State::Start;
+        self.framing = BodyFraming::None;
+        self.handler.on_message_complete()
+    }
+}
+
+/// Extract the version number from a `HTTP/x.y` string
+fn parse_version(version: &str) -> Option<&str> {
+    if !version.starts_with("HTTP/") {
+        return None;
+    }
+
+    let number = &version[5..];
+    let bytes = number.as_bytes();
+    let is_digit = |b: u8| b >= b'0' && b <= b'9';
+
+    if bytes.len() == 3 && is_digit(bytes[0]) && bytes[1] == b'.' && is_digit(bytes[2]) {
+        Some(number)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Handler writing down what the parser reports
+    #[derive(Default)]
+    struct Recorder {
+        events: Vec<String>,
+        body: Vec<u8>,
+    }
+
+    impl ParserHandler for Recorder {
+        fn on_method(&mut self, method: &str) -> Result<(), ParseError> {
+            self.events.push(format!("method {}", method));
+            Ok(())
+        }
+
+        fn on_url(&mut self, url: &str) -> Result<(), ParseError> {
+            self.events.push(format!("url {}", url));
+            Ok(())
+        }
+
+        fn on_query(&mut self, query: &str) -> Result<(), ParseError> {
+            self.events.push(format!("query {}", query));
+            Ok(())
+        }
+
+        fn on_http_version(&mut self, version: &str) -> Result<(), ParseError> {
+            self.events.push(format!("version {}", version));
+            Ok(())
+        }
+
+        fn on_status(&mut self, status: u16) -> Result<(), ParseError> {
+            self.events.push(format!("status {}", status));
+            Ok(())
+        }
+
+        fn on_header(&mut self, field: &str, values: Vec<&str>) -> Result<(), ParseError> {
+            self.events.push(format!("header {}: {}", field, values.join("|")));
+            Ok(())
+        }
+
+        fn on_body(&mut self, part: &[u8]) -> Result<(), ParseError> {
+            self.body.extend_from_slice(part);
+            Ok(())
+        }
+
+        fn on_headers_complete(&mut self) -> Result<(), ParseError> {
+            self.events.push("headers complete".to_owned());
+            Ok(())
+        }
+
+        fn on_message_complete(&mut self) -> Result<(), ParseError> {
+            self.events.push("complete".to_owned());
+            Ok(())
+        }
+    }
+
+    fn parse_request(data: &[u8]) -> Recorder {
+        let mut recorder = Recorder::default();
+        assert_eq!(Parser::request(&mut recorder).feed(data).unwrap(), Progress::Complete(data.len()));
+        recorder
+    }
+
+    /// Check that splitting `data` anywhere gives the same result as feeding it whole
+    fn assert_split_anywhere(data: &[u8]) {
+        let whole = parse_request(data);
+
+        for i in 1..data.len() {
+            let mut recorder = Recorder::default();
+            {
+                let mut parser = Parser::request(&mut recorder);
+                assert_eq!(parser.feed(&data[..i]).unwrap(), Progress::Incomplete, "split at {}", i);
+                assert_eq!(parser.feed(&data[i..]).unwrap(), Progress::Complete(data.len() - i), "split at {}", i);
             }
+            assert_eq!(recorder.events, whole.events, "split at {}", i);
+            assert_eq!(recorder.body, whole.body, "split at {}", i);
         }
+    }
+
+    #[test]
+    fn request_line_and_headers() {
+        let recorder = parse_request(b"GET /a%20b?x=1&y=%2F HTTP/1.1\r\nHost: example.com\r\nAccept: text/html, */*\r\n\r\n");
+
+        assert_eq!(recorder.events, vec![
+            "method GET",
+            "url /a%20b",
+            "query x=1&y=%2F",
+            "version 1.1",
+            "header Host: example.com",
+            "header Accept: text/html|*/*",
+            "headers complete",
+            "complete",
+        ]);
+    }
+
+    #[test]
+    fn request_split_at_every_byte() {
+        assert_split_anywhere(b"GET /index.html?q=1 HTTP/1.1\r\nHost: example.com\r\nConnection: keep-alive\r\n\r\n");
+        assert_split_anywhere(b"POST /form HTTP/1.1\r\nContent-Length: 11\r\n\r\nhello world");
+        assert_split_anywhere(b"POST /upload HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+                                5;ext=1\r\nhello\r\n6\r\n world\r\n0\r\nChecksum: abc\r\n\r\n");
+    }
+
+    #[test]
+    fn bare_newlines_and_leading_empty_lines() {
+        let recorder = parse_request(b"\r\n\nGET / HTTP/1.0\nHost: h\n\n");
+        assert_eq!(recorder.events[0], "method GET");
+        assert_eq!(recorder.events[3], "header Host: h");
+    }
+
+    #[test]
+    fn chunked_body_with_trailers() {
+        let recorder = parse_request(b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n\
+                                       3\r\nabc\r\nA\r\n0123456789\r\n0\r\nExpires: never\r\nX-Sum: 1\r\n\r\n");
+
+        assert_eq!(recorder.body, b"abc0123456789");
+        let trailers: Vec<&str> = recorder.events.iter()
+            .skip_while(|e| *e != "headers complete")
+            .map(|e| e.as_ref())
+            .collect();
+        assert_eq!(trailers, vec!["headers complete", "header Expires: never", "header X-Sum: 1", "complete"]);
+    }
+
+    #[test]
+    fn chunk_errors() {
+        let mut recorder = Recorder::default();
+        assert!(Parser::request(&mut recorder)
+            .feed(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n").is_err());
+
+        let mut recorder = Recorder::default();
+        assert!(Parser::request(&mut recorder)
+            .feed(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabcX\r\n").is_err());
+    }
+
+    #[test]
+    fn transfer_encoding_takes_precedence() {
+        let recorder = parse_request(b"POST / HTTP/1.1\r\nContent-Length: 100\r\nTransfer-Encoding: chunked\r\n\r\n\
+                                       2\r\nok\r\n0\r\n\r\n");
+        assert_eq!(recorder.body, b"ok");
+    }
+
+    #[test]
+    fn request_without_chunked_last_is_refused() {
+        let mut recorder = Recorder::default();
+        assert!(Parser::request(&mut recorder)
+            .feed(b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn pipelined_requests() {
+        let first = b"GET /one HTTP/1.1\r\nHost: h\r\n\r\n";
+        let second = b"POST /two HTTP/1.1\r\nContent-Length: 3\r\n\r\nabc";
+        let third = b"GET /three HTTP/1.1\r\n\r\n";
+        let mut data = Vec::new();
+        data.extend_from_slice(first);
+        data.extend_from_slice(second);
+        data.extend_from_slice(third);
+
+        let mut recorder = Recorder::default();
+        {
+            let mut parser = Parser::request(&mut recorder);
+            let mut pos = 0;
+            for expected in &[first.len(), second.len(), third.len()] {
+                assert_eq!(parser.feed(&data[pos..]).unwrap(), Progress::Complete(*expected));
+                pos += *expected;
+                assert!(parser.is_idle());
+            }
+        }
+
+        let urls: Vec<&str> = recorder.events.iter().filter(|e| e.starts_with("url ")).map(|e| e.as_ref()).collect();
+        assert_eq!(urls, vec!["url /one", "url /two", "url /three"]);
+        assert_eq!(recorder.body, b"abc");
+    }
+
+    #[test]
+    fn parse_leaves_the_next_request_in_the_reader() {
+        let mut reader: &[u8] = b"GET /one HTTP/1.1\r\n\r\nGET /two HTTP/1.1\r\n\r\n";
+
+        let mut recorder = Recorder::default();
+        Parser::request(&mut recorder).parse(&mut reader).unwrap();
+        assert_eq!(reader, b"GET /two HTTP/1.1\r\n\r\n");
+    }
+
+    #[test]
+    fn line_too_long() {
+        let mut data = b"GET /".to_vec();
+        data.extend(vec![b'a'; MAX_LINE_LENGTH]);
+
+        let mut recorder = Recorder::default();
+        let error = Parser::request(&mut recorder).feed(&data).unwrap_err();
+        assert_eq!(error.to_string(), "Line Too Long");
+
+        // the limit holds however the line is split
+        let mut recorder = Recorder::default();
+        let mut parser = Parser::request(&mut recorder);
+        let mut result = Ok(Progress::Incomplete);
+        for part in data.chunks(100) {
+            result = parser.feed(part);
+            if result.is_err() {
+                break;
+            }
+        }
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn malformed_requests() {
+        for data in &[&b"GET / HTTP/1.1 extra\r\n"[..], b"get / HTTP/1.1\r\n", b"GET / HTTP/11\r\n",
+                      b"GET ?x HTTP/1.1\r\n", b"GET / HTTP/1.1\r\nNo colon\r\n", b"GET / HTTP/1.1\r\n Host: h\r\n",
+                      b"POST / HTTP/1.1\r\nContent-Length: ten\r\n"] {
+            let mut recorder = Recorder::default();
+            assert!(Parser::request(&mut recorder).feed(data).is_err(), "{:?}", String::from_utf8_lossy(data));
+        }
+    }
+
+    #[test]
+    fn incomplete_request_at_end_of_input() {
+        let mut recorder = Recorder::default();
+        let mut parser = Parser::request(&mut recorder);
+        assert_eq!(parser.feed(b"GET / HTTP/1.1\r\nHo").unwrap(), Progress::Incomplete);
+        assert!(parser.finish().is_err());
+    }
+
+    #[test]
+    fn response_until_close() {
+        let mut recorder = Recorder::default();
+        {
+            let mut parser = Parser::response(&mut recorder);
+            assert_eq!(parser.feed(b"HTTP/1.0 200 OK\r\n\r\npart one, ").unwrap(), Progress::Incomplete);
+            assert_eq!(parser.feed(b"part two").unwrap(), Progress::Incomplete);
+            parser.finish().unwrap();
+        }
+        assert_eq!(recorder.body, b"part one, part two");
+        assert_eq!(recorder.events.last().unwrap(), "complete");
+    }
+
+    #[test]
+    fn responses_without_body() {
+        for data in &[&b"HTTP/1.1 304 Not Modified\r\nContent-Length: 10\r\n\r\n"[..],
+                      b"HTTP/1.1 204 No Content\r\n\r\n", b"HTTP/1.1 100 Continue\r\n\r\n"] {
+            let mut recorder = Recorder::default();
+            assert_eq!(Parser::response(&mut recorder).feed(data).unwrap(), Progress::Complete(data.len()));
+        }
+
+        let data = b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n";
+        let mut recorder = Recorder::default();
+        let mut parser = Parser::response(&mut recorder);
+        parser.with_head_request(true);
+        assert_eq!(parser.feed(data).unwrap(), Progress::Complete(data.len()));
+    }
+
+    #[test]
+    fn status_line() {
+        let mut recorder = Recorder::default();
+        Parser::response(&mut recorder).feed(b"HTTP/1.1 404\r\nContent-Length: 0\r\n\r\n").unwrap();
+        assert_eq!(&recorder.events[..2], &["version 1.1".to_owned(), "status 404".to_owned()]);
 
-        Ok(())
+        let mut recorder = Recorder::default();
+        assert!(Parser::response(&mut recorder).feed(b"HTTP/1.1 20 OK\r\n").is_err());
     }
 }
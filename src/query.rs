@@ -1,6 +1,7 @@
 use std::collections::HashMap;
 
 use regex::Regex;
+use url::percent_encoding;
 
 #[derive(Debug)]
 pub struct Query {
@@ -23,17 +24,16 @@ impl Query {
         if query_string.trim().len() > 0 {
             let re = Regex::new(r"([^=&]+)(=([^&]*))?").unwrap();
             for cap in re.captures_iter(query_string) {
-                let key = cap.at(1).unwrap();
-                // TODO: Decode query string (see this http://unixpapa.com/js/querystring.html)
-                let val = cap.at(3).unwrap_or("");
-                let mut query_vec = data.entry(key.to_owned()).or_insert(Vec::new());
-                query_vec.push(val.to_owned());
+                let key = decode_component(cap.at(1).unwrap());
+                let val = decode_component(cap.at(3).unwrap_or(""));
+                let mut query_vec = data.entry(key).or_insert(Vec::new());
+                query_vec.push(val);
             }
         }
 
         Query {
             data: data,
-            query_string: None,
+            query_string: Some(query_string.to_owned()),
         }
     }
 
@@ -58,6 +58,11 @@ impl Query {
     }
 }
 
+/// Decode a key or value of a form-encoded query, where `+` stands for a space
+fn decode_component(component: &str) -> String {
+    percent_encoding::lossy_utf8_percent_decode(component.replace("+", " ").as_bytes())
+}
+
 fn format_query_param(k: &str, v: &Vec<String>) -> String {
     let mut result = String::new();
 
@@ -0,0 +1,169 @@
+/// Most ranges accepted in a single request, more are treated as abuse
+/// and the whole representation is sent instead
+const MAX_RANGES: usize = 64;
+
+/// Satisfiable byte range of a representation, both ends inclusive
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    pub fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    /// Value of the `Content-Range` header for this range
+    pub fn content_range(&self, length: u64) -> String {
+        format!("bytes {}-{}/{}", self.start, self.end, length)
+    }
+}
+
+/// What a `Range` header asks for, according to RFC 7233
+#[derive(Debug, PartialEq)]
+pub enum Ranges {
+    /// No usable range, the whole representation is sent
+    Full,
+    /// At least one range can be served
+    Satisfiable(Vec<ByteRange>),
+    /// None of the ranges overlap the representation
+    Unsatisfiable,
+}
+
+/// Parse the value of a `Range` header for a representation of `length` bytes
+///
+/// Headers with an unknown unit or invalid syntax are ignored, as the RFC
+/// requires
+pub fn parse(header: &str, length: u64) -> Ranges {
+    let header = header.trim();
+    if !header.starts_with("bytes=") {
+        return Ranges::Full;
+    }
+
+    let specs: Vec<&str> = header[6..].split(',')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .collect();
+
+    if specs.is_empty() || specs.len() > MAX_RANGES {
+        return Ranges::Full;
+    }
+
+    let mut ranges = Vec::new();
+
+    for spec in specs {
+        let dash = match spec.find('-') {
+            Some(dash) => dash,
+            None => return Ranges::Full,
+        };
+        let (first, last) = (spec[..dash].trim(), spec[dash + 1..].trim());
+
+        if first.is_empty() {
+            // suffix range, the last bytes of the representation
+            let suffix: u64 = match last.parse() {
+                Ok(suffix) => suffix,
+                Err(_) => return Ranges::Full,
+            };
+
+            if suffix > 0 && length > 0 {
+                let start = if suffix < length { length - suffix } else { 0 };
+                ranges.push(ByteRange { start: start, end: length - 1 });
+            }
+            continue;
+        }
+
+        let start: u64 = match first.parse() {
+            Ok(start) => start,
+            Err(_) => return Ranges::Full,
+        };
+
+        let end: u64 = if last.is_empty() {
+            u64::max_value()
+        } else {
+            match last.parse() {
+                Ok(end) => end,
+                Err(_) => return Ranges::Full,
+            }
+        };
+
+        if end < start {
+            return Ranges::Full;
+        }
+
+        if start < length {
+            let end = if end < length { end } else { length - 1 };
+            ranges.push(ByteRange { start: start, end: end });
+        }
+    }
+
+    if ranges.is_empty() {
+        Ranges::Unsatisfiable
+    } else {
+        Ranges::Satisfiable(ranges)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn satisfiable(ranges: &[(u64, u64)]) -> Ranges {
+        Ranges::Satisfiable(ranges.iter().map(|&(start, end)| ByteRange { start: start, end: end }).collect())
+    }
+
+    #[test]
+    fn single_ranges() {
+        assert_eq!(parse("bytes=0-499", 1000), satisfiable(&[(0, 499)]));
+        assert_eq!(parse("bytes=500-", 1000), satisfiable(&[(500, 999)]));
+        assert_eq!(parse("bytes=-200", 1000), satisfiable(&[(800, 999)]));
+        assert_eq!(parse(" bytes= 10 - 20 ", 1000), satisfiable(&[(10, 20)]));
+    }
+
+    #[test]
+    fn ranges_are_clamped_to_the_length() {
+        assert_eq!(parse("bytes=900-5000", 1000), satisfiable(&[(900, 999)]));
+        assert_eq!(parse("bytes=-5000", 1000), satisfiable(&[(0, 999)]));
+        assert_eq!(parse("bytes=0-18446744073709551615", 10), satisfiable(&[(0, 9)]));
+    }
+
+    #[test]
+    fn several_ranges() {
+        assert_eq!(parse("bytes=0-0,-1, 5-6", 10), satisfiable(&[(0, 0), (9, 9), (5, 6)]));
+
+        // ranges past the end are dropped while the others are served
+        assert_eq!(parse("bytes=2000-3000,0-1", 1000), satisfiable(&[(0, 1)]));
+    }
+
+    #[test]
+    fn unsatisfiable() {
+        assert_eq!(parse("bytes=1000-", 1000), Ranges::Unsatisfiable);
+        assert_eq!(parse("bytes=-0", 1000), Ranges::Unsatisfiable);
+        assert_eq!(parse("bytes=0-10", 0), Ranges::Unsatisfiable);
+        assert_eq!(parse("bytes=-10", 0), Ranges::Unsatisfiable);
+    }
+
+    #[test]
+    fn invalid_headers_are_ignored() {
+        for header in &["items=0-10", "bytes=", "bytes=,", "bytes=10", "bytes=a-b", "bytes=5-1",
+                        "bytes=0-1,x", "bytes=--5", "bytes=-1-2"] {
+            assert_eq!(parse(header, 1000), Ranges::Full, "{}", header);
+        }
+    }
+
+    #[test]
+    fn too_many_ranges() {
+        let header = format!("bytes={}", vec!["0-0"; MAX_RANGES + 1].join(","));
+        assert_eq!(parse(&header, 1000), Ranges::Full);
+
+        let header = format!("bytes={}", vec!["0-0"; MAX_RANGES].join(","));
+        assert!(parse(&header, 1000) != Ranges::Full);
+    }
+
+    #[test]
+    fn byte_range() {
+        let range = ByteRange { start: 10, end: 19 };
+        assert_eq!(range.len(), 10);
+        assert_eq!(range.content_range(100), "bytes 10-19/100");
+    }
+}
@@ -1,6 +1,8 @@
-use std::io::Read;
+use std::io::{Cursor, Read};
 use std::net::{SocketAddr, TcpStream};
 
+use url::percent_encoding;
+
 use super::headers::Headers;
 use super::query::Query;
 
@@ -10,30 +12,39 @@ pub struct Request {
     method: String,
     scheme: String,
     path: Vec<String>,
+    raw_path: Vec<String>,
     path_str: String,
     query: Option<Query>,
     headers: Headers,
     content_length: Option<u64>,
+    body: Cursor<Vec<u8>>,
     stream: TcpStream,
 }
 
 impl Request {
     pub fn new(method: &str, scheme: &str, url: &str, query: Option<Query>,
                version: (u16, u16), headers: Headers,
-               content_length: Option<u64>,
+               content_length: Option<u64>, body: Vec<u8>,
                stream: &TcpStream) -> Self {
 
-       let path = url[1..url.len()].split('/').map(|x| x.to_owned()).collect();
+       // each component is decoded on its own, so one may contain a slash
+       let relative = if url.starts_with('/') { &url[1..] } else { url };
+       let raw_path: Vec<String> = relative.split('/').map(|x| x.to_owned()).collect();
+       let path = raw_path.iter()
+           .map(|x| percent_encoding::lossy_utf8_percent_decode(x.as_bytes()))
+           .collect();
 
        Request {
            http_version: version,
            method: method.to_owned(),
            scheme: scheme.to_owned(),
            path: path,
-           path_str: url.to_owned(),
+           raw_path: raw_path,
+           path_str: percent_encoding::lossy_utf8_percent_decode(url.as_bytes()),
            headers: headers,
            query: query,
            content_length: content_length,
+           body: Cursor::new(body),
            stream: stream.try_clone().unwrap(),
        }
    }
@@ -62,6 +73,13 @@ impl Request {
         self.path.iter().map(|i| i.as_ref()).collect()
     }
 
+    /// Path components as sent, still percent-encoded
+    ///
+    /// Unlike `path_components` this keeps bytes that are not valid UTF-8
+    pub fn raw_path_components(&self) -> Vec<&str> {
+        self.raw_path.iter().map(|i| i.as_ref()).collect()
+    }
+
     pub fn query(&self) -> &Option<Query> {
         &self.query
     }
@@ -78,7 +96,26 @@ impl Request {
         &self.headers
     }
 
+    /// Whether the client wants the connection kept open after this request
+    ///
+    /// HTTP/1.1 connections are persistent unless the client sends
+    /// `Connection: close`, while HTTP/1.0 clients have to ask for it
+    /// with `Connection: keep-alive`
+    pub fn keep_alive(&self) -> bool {
+        let tokens: Vec<String> = match self.headers.find("Connection") {
+            Some(values) => values.iter().map(|v| v.to_lowercase()).collect(),
+            None => Vec::new(),
+        };
+
+        if self.http_version >= (1, 1) {
+            !tokens.iter().any(|t| t == "close")
+        } else {
+            tokens.iter().any(|t| t == "keep-alive")
+        }
+    }
+
+    /// Reader over the message body, already decoded from its transfer framing
     pub fn body<'a>(&'a mut self) -> &'a mut Read {
-        &mut self.stream
+        &mut self.body
     }
 }
@@ -0,0 +1,361 @@
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Component, Path, PathBuf};
+
+use url::percent_encoding;
+
+/// Whether symbolic links inside the served tree are followed
+///
+/// Whatever the policy, a path that resolves outside the root is refused
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SymlinkPolicy {
+    Follow,
+    /// Follow a link only when it has the same owner as its target
+    FollowIfOwnerMatches,
+    Never,
+}
+
+#[cfg(unix)]
+fn to_os_string(bytes: Vec<u8>) -> Option<OsString> {
+    use std::os::unix::ffi::OsStringExt;
+    Some(OsString::from_vec(bytes))
+}
+
+#[cfg(not(unix))]
+fn to_os_string(bytes: Vec<u8>) -> Option<OsString> {
+    String::from_utf8(bytes).ok().map(OsString::from)
+}
+
+/// Decode percent-encoded path components and remove `.` and `..` segments
+/// as RFC 3986 section 5.2.4 does, so the result can never climb above the root
+///
+/// Components holding a slash or a NUL byte, which can only come from
+/// percent-encoding, are rejected
+pub fn normalize(components: &[&str]) -> Result<Vec<OsString>, io::Error> {
+    let mut normalized = Vec::new();
+
+    for component in components {
+        let bytes = percent_encoding::percent_decode(component.as_bytes());
+
+        if bytes.iter().any(|&b| b == b'/' || b == 0) {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "Invalid path component"));
+        }
+
+        match &bytes[..] {
+            b"" | b"." => continue,
+            b".." => {
+                normalized.pop();
+                continue;
+            },
+            _ => {},
+        }
+
+        let component = match to_os_string(bytes) {
+            Some(component) => component,
+            None => return Err(io::Error::new(ErrorKind::InvalidInput, "Invalid path component")),
+        };
+
+        // anything the platform would read as a prefix or root, like `C:`
+        // or a backslash on Windows, could escape the root
+        let valid = {
+            let mut parts = Path::new(&component).components();
+            match (parts.next(), parts.next()) {
+                (Some(Component::Normal(_)), None) => true,
+                _ => false,
+            }
+        };
+
+        if !valid {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "Invalid path component"));
+        }
+        normalized.push(component);
+    }
+
+    Ok(normalized)
+}
+
+#[cfg(unix)]
+fn same_owner(link: &fs::Metadata, target: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    link.uid() == target.uid()
+}
+
+#[cfg(not(unix))]
+fn same_owner(_link: &fs::Metadata, _target: &fs::Metadata) -> bool {
+    false
+}
+
+/// Refuse `path` if it is a symbolic link the policy does not let us follow
+fn check_symlink(path: &Path, symlinks: SymlinkPolicy) -> Result<(), io::Error> {
+    let metadata = try!(fs::symlink_metadata(path));
+    if !metadata.file_type().is_symlink() {
+        return Ok(());
+    }
+
+    let allowed = match symlinks {
+        SymlinkPolicy::Follow => true,
+        SymlinkPolicy::FollowIfOwnerMatches => same_owner(&metadata, &try!(fs::metadata(path))),
+        SymlinkPolicy::Never => false,
+    };
+
+    if allowed {
+        Ok(())
+    } else {
+        Err(io::Error::new(ErrorKind::PermissionDenied, "Symbolic link not allowed"))
+    }
+}
+
+/// Where `path` leads once every symbolic link is followed, relative to
+/// the root, failing with `PermissionDenied` when that is outside the root
+pub fn target(root: &Path, path: &Path) -> Result<PathBuf, io::Error> {
+    let canonical_root = try!(fs::canonicalize(root));
+    let canonical = try!(fs::canonicalize(path));
+
+    match canonical.strip_prefix(&canonical_root) {
+        Ok(relative) => Ok(relative.to_path_buf()),
+        Err(_) => Err(io::Error::new(ErrorKind::PermissionDenied, "Path outside of root")),
+    }
+}
+
+fn check_inside_root(root: &Path, path: &Path) -> Result<(), io::Error> {
+    target(root, path).map(|_| ())
+}
+
+/// Map percent-encoded request path components to a file under `root`
+///
+/// Fails with `InvalidInput` for malformed components and with
+/// `PermissionDenied` when a symbolic link is refused or the path
+/// resolves outside the root
+pub fn resolve(root: &Path, components: &[&str], symlinks: SymlinkPolicy) -> Result<PathBuf, io::Error> {
+    let components = try!(normalize(components));
+
+    let mut resource = root.to_path_buf();
+    for component in components {
+        resource.push(&component);
+        try!(check_symlink(&resource, symlinks));
+    }
+
+    try!(check_inside_root(root, &resource));
+    Ok(resource)
+}
+
+/// Resolve the file named like an already resolved `resource` with `suffix`
+/// appended, such as `app.js.gz` next to `app.js`
+pub fn sibling(root: &Path, resource: &Path, suffix: &str, symlinks: SymlinkPolicy) -> Result<PathBuf, io::Error> {
+    let mut name = match resource.file_name() {
+        Some(name) => name.to_os_string(),
+        None => return Err(io::Error::new(ErrorKind::InvalidInput, "Path without a file name")),
+    };
+    name.push(suffix);
+
+    let sibling = resource.with_file_name(name);
+    try!(check_symlink(&sibling, symlinks));
+    try!(check_inside_root(root, &sibling));
+
+    Ok(sibling)
+}
+
+/// Check a path found under an already resolved directory, such as while
+/// walking a tree, against the symbolic link policy and the root
+pub fn verify(root: &Path, path: &Path, symlinks: SymlinkPolicy) -> Result<(), io::Error> {
+    try!(check_symlink(path, symlinks));
+    check_inside_root(root, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs::File;
+    use std::process;
+
+    fn normalized(components: &[&str]) -> Vec<String> {
+        normalize(components).unwrap().iter().map(|c| c.to_string_lossy().into_owned()).collect()
+    }
+
+    fn error_kind<T>(result: Result<T, io::Error>) -> ErrorKind {
+        match result {
+            Ok(_) => panic!("expected an error"),
+            Err(e) => e.kind(),
+        }
+    }
+
+    #[test]
+    fn decodes_components() {
+        assert_eq!(normalized(&["a%20b", "caf%C3%A9"]), vec!["a b", "café"]);
+    }
+
+    #[test]
+    fn empty_and_dot_segments() {
+        assert_eq!(normalized(&["", "a", ".", "", "b", "%2e"]), vec!["a", "b"]);
+        assert!(normalize(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn dot_dot_segments_never_climb_above_the_root() {
+        assert_eq!(normalized(&["a", "b", "..", "c"]), vec!["a", "c"]);
+        assert_eq!(normalized(&["..", "..", "etc", "passwd"]), vec!["etc", "passwd"]);
+        assert_eq!(normalized(&["a", "%2e%2e", "%2E%2E", "%2e.", "b"]), vec!["b"]);
+    }
+
+    #[test]
+    fn encoded_separators_and_nul_are_refused() {
+        for component in &["a%2Fb", "%2f", "..%2F..", "a%00", "%00"] {
+            assert_eq!(error_kind(normalize(&["dir", component])), ErrorKind::InvalidInput, "{}", component);
+        }
+    }
+
+    /// Served tree with symbolic links inside it and out of it, removed when dropped
+    ///
+    /// ```text
+    /// base/root/pub/file.txt
+    /// base/root/inner -> pub/file.txt
+    /// base/root/dirlink -> pub
+    /// base/root/escape -> ../outside/secret
+    /// base/outside/secret
+    /// ```
+    struct Tree {
+        base: PathBuf,
+        root: PathBuf,
+    }
+
+    impl Tree {
+        fn new(name: &str) -> Tree {
+            let base = env::temp_dir().join(format!("mahardhika-resolve-{}-{}", name, process::id()));
+            let _ = fs::remove_dir_all(&base);
+            let root = base.join("root");
+
+            fs::create_dir_all(root.join("pub")).unwrap();
+            fs::create_dir_all(base.join("outside")).unwrap();
+            File::create(root.join("pub/file.txt")).unwrap();
+            File::create(root.join("pub/file.txt.gz")).unwrap();
+            File::create(base.join("outside/secret")).unwrap();
+
+            Tree { base: base, root: root }
+        }
+    }
+
+    impl Drop for Tree {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.base);
+        }
+    }
+
+    #[test]
+    fn resolves_under_the_root() {
+        let tree = Tree::new("plain");
+
+        let path = resolve(&tree.root, &["pub", "file.txt"], SymlinkPolicy::Never).unwrap();
+        assert_eq!(path, tree.root.join("pub/file.txt"));
+
+        let path = resolve(&tree.root, &["pub", "%2e%2e", "pub", "file.txt"], SymlinkPolicy::Never).unwrap();
+        assert_eq!(path, tree.root.join("pub/file.txt"));
+
+        // climbing out and back in looks for `root` inside the root
+        assert_eq!(error_kind(resolve(&tree.root, &["..", "root", "pub", "file.txt"], SymlinkPolicy::Never)), ErrorKind::NotFound);
+
+        assert_eq!(error_kind(resolve(&tree.root, &["..", "outside", "secret"], SymlinkPolicy::Follow)), ErrorKind::NotFound);
+        assert_eq!(error_kind(resolve(&tree.root, &["a%2F..%2F..%2Foutside"], SymlinkPolicy::Follow)), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn sibling_files() {
+        let tree = Tree::new("sibling");
+        let file = tree.root.join("pub/file.txt");
+
+        assert_eq!(sibling(&tree.root, &file, ".gz", SymlinkPolicy::Never).unwrap(), tree.root.join("pub/file.txt.gz"));
+        assert_eq!(error_kind(sibling(&tree.root, &file, ".br", SymlinkPolicy::Never)), ErrorKind::NotFound);
+    }
+
+    #[cfg(unix)]
+    mod symlinks {
+        use super::*;
+        use std::ffi::CString;
+        use std::os::unix::ffi::{OsStrExt, OsStringExt};
+        use std::os::unix::fs::symlink;
+
+        use libc;
+
+        fn linked_tree(name: &str) -> Tree {
+            let tree = Tree::new(name);
+            symlink("pub/file.txt", tree.root.join("inner")).unwrap();
+            symlink("pub", tree.root.join("dirlink")).unwrap();
+            symlink("../outside/secret", tree.root.join("escape")).unwrap();
+            tree
+        }
+
+        #[test]
+        fn follow() {
+            let tree = linked_tree("follow");
+            let policy = SymlinkPolicy::Follow;
+
+            assert!(resolve(&tree.root, &["inner"], policy).is_ok());
+            assert!(resolve(&tree.root, &["dirlink", "file.txt"], policy).is_ok());
+            assert!(verify(&tree.root, &tree.root.join("inner"), policy).is_ok());
+            assert_eq!(target(&tree.root, &tree.root.join("dirlink/file.txt")).unwrap(), PathBuf::from("pub/file.txt"));
+        }
+
+        #[test]
+        fn never() {
+            let tree = linked_tree("never");
+            let policy = SymlinkPolicy::Never;
+
+            assert_eq!(error_kind(resolve(&tree.root, &["inner"], policy)), ErrorKind::PermissionDenied);
+            assert_eq!(error_kind(resolve(&tree.root, &["dirlink", "file.txt"], policy)), ErrorKind::PermissionDenied);
+            assert_eq!(error_kind(verify(&tree.root, &tree.root.join("inner"), policy)), ErrorKind::PermissionDenied);
+            assert!(verify(&tree.root, &tree.root.join("pub/file.txt"), policy).is_ok());
+        }
+
+        #[test]
+        fn follow_if_owner_matches() {
+            let tree = linked_tree("owner");
+            let policy = SymlinkPolicy::FollowIfOwnerMatches;
+
+            assert!(resolve(&tree.root, &["inner"], policy).is_ok());
+            assert!(verify(&tree.root, &tree.root.join("inner"), policy).is_ok());
+
+            // only root can give a link away to another user
+            if unsafe { libc::geteuid() } != 0 {
+                return;
+            }
+
+            let link = tree.root.join("inner");
+            let path = CString::new(link.as_os_str().as_bytes()).unwrap();
+            assert_eq!(unsafe { libc::lchown(path.as_ptr(), 65534, 65534) }, 0);
+
+            assert_eq!(error_kind(resolve(&tree.root, &["inner"], policy)), ErrorKind::PermissionDenied);
+            assert_eq!(error_kind(verify(&tree.root, &link, policy)), ErrorKind::PermissionDenied);
+        }
+
+        #[test]
+        fn links_out_of_the_root_are_refused() {
+            let tree = linked_tree("escape");
+
+            for policy in &[SymlinkPolicy::Follow, SymlinkPolicy::FollowIfOwnerMatches, SymlinkPolicy::Never] {
+                assert_eq!(error_kind(resolve(&tree.root, &["escape"], *policy)), ErrorKind::PermissionDenied);
+                assert_eq!(error_kind(verify(&tree.root, &tree.root.join("escape"), *policy)), ErrorKind::PermissionDenied);
+            }
+            assert_eq!(error_kind(target(&tree.root, &tree.root.join("escape"))), ErrorKind::PermissionDenied);
+        }
+
+        #[test]
+        fn links_out_of_the_root_through_a_linked_root() {
+            let tree = linked_tree("linked-root");
+            let linked_root = tree.base.join("root-link");
+            symlink("root", &linked_root).unwrap();
+
+            // the root is compared once its own links are followed too
+            assert!(resolve(&linked_root, &["inner"], SymlinkPolicy::Follow).is_ok());
+            assert_eq!(error_kind(resolve(&linked_root, &["escape"], SymlinkPolicy::Follow)), ErrorKind::PermissionDenied);
+        }
+
+        #[test]
+        fn non_utf8_names() {
+            let tree = Tree::new("bytes");
+            File::create(tree.root.join(OsString::from_vec(b"bad\xff".to_vec()))).unwrap();
+
+            assert!(resolve(&tree.root, &["bad%FF"], SymlinkPolicy::Never).is_ok());
+        }
+    }
+}
@@ -1,9 +1,12 @@
 use std::error::Error;
-use std::io::{self, Write, BufWriter};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write, BufWriter, ErrorKind};
 use std::net::{TcpStream, Shutdown};
 
+use libc;
 use time;
 
+use super::compress::{self, Encoder, Policy};
 use super::headers::Headers;
 
 #[derive(Debug)]
@@ -14,6 +17,9 @@ pub struct Response {
     headers: Headers,
     stream: BufWriter<TcpStream>,
     headers_written: bool,
+    keep_alive: bool,
+    head_request: bool,
+    compression: Option<Policy>,
 }
 
 impl Response {
@@ -27,6 +33,9 @@ impl Response {
             headers: Headers::new(),
             stream: BufWriter::new(stream),
             headers_written: false,
+            keep_alive: false,
+            head_request: false,
+            compression: None,
         })
     }
 
@@ -73,14 +82,147 @@ impl Response {
         self
     }
 
+    /// Whether the connection stays open once this response is sent
+    ///
+    /// Before the response is started this is what the server asked for,
+    /// afterwards it tells if the connection was actually kept open
+    pub fn keep_alive(&self) -> bool {
+        self.keep_alive
+    }
+
+    pub fn with_keep_alive(&mut self, keep_alive: bool) -> &mut Self {
+        if self.headers_written {
+            panic!("Cannot write header to started response")
+        }
+
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    /// Mark the response as answering a `HEAD` request
+    ///
+    /// The headers are sent as they would be for `GET`, but anything
+    /// written to the body is discarded
+    pub fn with_head_request(&mut self, head_request: bool) -> &mut Self {
+        if self.headers_written {
+            panic!("Cannot write header to started response")
+        }
+
+        self.head_request = head_request;
+        self
+    }
+
+    /// Let the body be compressed as `policy` says, when its headers show
+    /// it is worth it
+    pub fn with_compression(&mut self, policy: Policy) -> &mut Self {
+        if self.headers_written {
+            panic!("Cannot write header to started response")
+        }
+
+        self.compression = Some(policy);
+        self
+    }
+
+    /// Whether the status code allows the response to carry a body
+    fn has_body(&self) -> bool {
+        !(self.status / 100 == 1 || self.status == 204 || self.status == 304)
+    }
+
+    /// Decide whether to compress the body now that the headers are known,
+    /// adjusting them to describe the compressed body
+    fn start_encoder(&mut self) -> Option<Encoder> {
+        let policy = match self.compression {
+            Some(policy) => policy,
+            None => return None,
+        };
+
+        // partial content is a slice of the uncompressed representation
+        let compressible = self.has_body()
+            && self.status != 206
+            && !self.headers.has("Content-Encoding")
+            && !self.headers.has("Content-Range")
+            && self.headers.value("Content-Type").map(|t| compress::is_compressible(&t)).unwrap_or(false);
+
+        if !compressible {
+            return None;
+        }
+
+        let varies = self.headers.find("Vary")
+            .map(|values| values.iter().any(|v| v.eq_ignore_ascii_case("Accept-Encoding")))
+            .unwrap_or(false);
+        if !varies {
+            self.headers.insert("Vary", "Accept-Encoding");
+        }
+
+        let coding = match policy.coding {
+            Some(coding) => coding,
+            None => return None,
+        };
+
+        let large_enough = self.headers.value("Content-Length")
+            .and_then(|length| length.trim().parse::<u64>().ok())
+            .map(|length| length >= policy.min_size)
+            .unwrap_or(true);
+
+        if !large_enough {
+            return None;
+        }
+
+        // the length is no longer known, so the body ends up chunked
+        self.headers.remove("Content-Length");
+        self.headers.insert("Content-Encoding", coding.name());
+
+        // the compressed bytes are not the ones a strong validator vouches for
+        if let Some(etag) = self.headers.value("ETag") {
+            if !etag.starts_with("W/") {
+                self.headers.remove("ETag");
+                self.headers.insert("ETag", &format!("W/{}", etag));
+            }
+        }
+
+        Some(Encoder::new(coding))
+    }
+
+    /// Write the status line and headers, then let `cb` write the body
+    ///
+    /// When talking HTTP/1.1 and no `Content-Length` was set the body is
+    /// sent with chunked transfer-encoding, so the connection can be kept
+    /// open even when the length is not known up front
     pub fn start<F>(&mut self, cb: F) -> Result<(), io::Error>
-            where F: FnOnce(&mut BufWriter<TcpStream>) -> Result<(), io::Error> {
+            where F: FnOnce(&mut BodyWriter) -> Result<(), io::Error> {
         if self.headers_written {
             panic!("Response already started");
         }
 
-        self.with_header("Date", &time::now_utc().rfc822().to_string())
-            .with_header("Connection", "close");
+        self.with_header("Date", &time::now_utc().rfc822().to_string());
+        let encoder = self.start_encoder();
+
+        let chunked = self.http_version != "1.0"
+            && self.has_body()
+            && !self.head_request
+            && !self.headers.has("Content-Length")
+            && !self.headers.has("Transfer-Encoding");
+
+        if chunked {
+            self.with_header("Transfer-Encoding", "chunked");
+        }
+
+        // without a known length the end of the body is marked by closing the connection
+        let close_requested = match self.headers.find("Connection") {
+            Some(values) => values.iter().any(|v| v.to_lowercase() == "close"),
+            None => false,
+        };
+        let delimited = chunked || !self.has_body() || self.head_request
+            || self.headers.has("Content-Length");
+        self.keep_alive = self.keep_alive && !close_requested && delimited;
+
+        if !self.keep_alive {
+            if !close_requested {
+                self.with_header("Connection", "close");
+            }
+        } else if self.http_version == "1.0" {
+            self.with_header("Connection", "keep-alive");
+        }
 
         self.headers_written = true;
 
@@ -90,8 +232,212 @@ impl Response {
         try!(self.stream.write(format!("{}", self.headers.to_string()).as_bytes()));
         try!(self.stream.write(b"\r\n"));
 
-        let result = cb(&mut self.stream);
-        try!(self.stream.get_mut().shutdown(Shutdown::Both));
+        let discard = self.head_request || !self.has_body();
+        let result = {
+            let mut body = BodyWriter {
+                stream: &mut self.stream,
+                chunked: chunked,
+                discard: discard,
+                trailers: Headers::new(),
+                encoder: encoder,
+            };
+
+            match cb(&mut body) {
+                Ok(()) => body.finish(),
+                Err(e) => Err(e),
+            }
+        };
+        if result.is_err() {
+            self.keep_alive = false;
+        }
+
+        try!(self.stream.flush());
+        if !self.keep_alive {
+            try!(self.stream.get_mut().shutdown(Shutdown::Both));
+        }
         result
     }
 }
+
+/// Most bytes handed to a single sendfile(2) call, the limit of Linux
+#[cfg(target_os = "linux")]
+const MAX_SENDFILE: u64 = 0x7ffff000;
+
+/// Send up to `len` bytes of `file` from `offset` straight to the socket,
+/// without them going through user space
+#[cfg(target_os = "linux")]
+fn sendfile(socket: &TcpStream, file: &File, offset: u64, len: u64) -> Result<u64, io::Error> {
+    use std::cmp;
+    use std::os::unix::io::AsRawFd;
+
+    let mut offset = offset as libc::off_t;
+    let count = cmp::min(len, MAX_SENDFILE) as libc::size_t;
+
+    let sent = unsafe { libc::sendfile(socket.as_raw_fd(), file.as_raw_fd(), &mut offset, count) };
+    if sent < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(sent as u64)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn sendfile(_socket: &TcpStream, _file: &File, _offset: u64, _len: u64) -> Result<u64, io::Error> {
+    Err(io::Error::from_raw_os_error(libc::ENOSYS))
+}
+
+/// Whether sendfile(2) failed because it cannot be used for this file or
+/// system, rather than because of the connection
+fn sendfile_unsupported(error: &io::Error) -> bool {
+    match error.raw_os_error() {
+        Some(code) => code == libc::EINVAL || code == libc::ENOSYS,
+        None => false,
+    }
+}
+
+/// Writer handed to `Response::start` for the response body
+///
+/// Writes go straight to the connection, or are framed as chunks when the
+/// response uses chunked transfer-encoding, after going through the
+/// compressor if the response is compressed. They are dropped when the
+/// response carries no body, as for `HEAD` requests
+pub struct BodyWriter<'a> {
+    stream: &'a mut BufWriter<TcpStream>,
+    chunked: bool,
+    discard: bool,
+    trailers: Headers,
+    encoder: Option<Encoder>,
+}
+
+impl<'a> BodyWriter<'a> {
+    pub fn is_chunked(&self) -> bool {
+        self.chunked
+    }
+
+    /// Add a trailer field, sent after the last chunk
+    ///
+    /// Trailers can only be sent with chunked responses and are silently
+    /// dropped otherwise
+    pub fn with_trailer(&mut self, name: &str, value: &str) -> &mut Self {
+        self.trailers.insert(name, value);
+        self
+    }
+
+    /// Send `len` bytes of `file` starting at `start`
+    ///
+    /// When the body is neither chunked nor compressed the bytes go from
+    /// the file to the socket with sendfile(2), and are copied through the
+    /// writer otherwise or where it is not available
+    pub fn copy_file(&mut self, file: &mut File, start: u64, len: u64) -> Result<(), io::Error> {
+        if self.discard {
+            return Ok(());
+        }
+
+        if self.chunked || self.encoder.is_some() {
+            return self.copy_file_through(file, start, len);
+        }
+
+        // the headers and anything written before have to go out first
+        try!(self.stream.flush());
+
+        let mut sent = 0;
+        while sent < len {
+            match sendfile(self.stream.get_ref(), file, start + sent, len - sent) {
+                Ok(0) => return Err(io::Error::new(ErrorKind::UnexpectedEof, "File ended before its length")),
+                Ok(count) => sent += count,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {},
+                Err(ref e) if sent == 0 && sendfile_unsupported(e) => {
+                    return self.copy_file_through(file, start, len);
+                },
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn copy_file_through(&mut self, file: &mut File, start: u64, len: u64) -> Result<(), io::Error> {
+        try!(file.seek(SeekFrom::Start(start)));
+        let copied = try!(io::copy(&mut file.take(len), self));
+
+        if copied < len {
+            return Err(io::Error::new(ErrorKind::UnexpectedEof, "File ended before its length"));
+        }
+        Ok(())
+    }
+
+    /// Write the end of the compressed stream, the terminating zero-length
+    /// chunk and the trailers
+    fn finish(&mut self) -> Result<(), io::Error> {
+        if let Some(encoder) = self.encoder.take() {
+            let tail = try!(encoder.finish());
+            if !self.discard {
+                try!(self.write_encoded(&tail));
+            }
+        }
+
+        if self.chunked && !self.discard {
+            try!(self.stream.write_all(b"0\r\n"));
+            try!(self.stream.write_all(self.trailers.to_string().as_bytes()));
+            try!(self.stream.write_all(b"\r\n"));
+        }
+        Ok(())
+    }
+
+    /// Send data that went through the compressor, framed as a chunk if needed
+    fn write_encoded(&mut self, buf: &[u8]) -> Result<(), io::Error> {
+        if buf.is_empty() {
+            return Ok(());
+        }
+
+        if self.chunked {
+            try!(self.stream.write_all(format!("{:x}\r\n", buf.len()).as_bytes()));
+            try!(self.stream.write_all(buf));
+            self.stream.write_all(b"\r\n")
+        } else {
+            self.stream.write_all(buf)
+        }
+    }
+}
+
+impl<'a> Write for BodyWriter<'a> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
+        if self.discard {
+            return Ok(buf.len());
+        }
+
+        let compressed = match self.encoder {
+            Some(ref mut encoder) => Some(try!(encoder.compress(buf))),
+            None => None,
+        };
+        if let Some(compressed) = compressed {
+            try!(self.write_encoded(&compressed));
+            return Ok(buf.len());
+        }
+
+        if !self.chunked {
+            return self.stream.write(buf);
+        }
+
+        // a zero-length chunk would end the body
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        try!(self.stream.write_all(format!("{:x}\r\n", buf.len()).as_bytes()));
+        try!(self.stream.write_all(buf));
+        try!(self.stream.write_all(b"\r\n"));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        if !self.discard {
+            let flushed = match self.encoder {
+                Some(ref mut encoder) => try!(encoder.flush()),
+                None => Vec::new(),
+            };
+            try!(self.write_encoded(&flushed));
+        }
+        self.stream.flush()
+    }
+}
@@ -1,6 +1,9 @@
 use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, ErrorKind, Write};
 use std::net::{TcpListener, TcpStream};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 use threadpool::ThreadPool;
 
 use ::response::Response;
@@ -12,15 +15,30 @@ use ::parser::{Parser, ParserHandler, ParseError};
 
 #[derive(Default)]
 struct HttpParserHandler {
+    started: bool,
     method: String,
     url: String,
     query: Option<String>,
     version: String,
     headers: HashMap<String, Vec<String>>,
+    content_length: Option<u64>,
+    body: Vec<u8>,
+    max_body_size: u64,
+    /// The body was refused for being larger than `max_body_size`
+    too_large: bool,
 }
 
 impl HttpParserHandler {
-    pub fn build_request(&self, stream: &TcpStream) -> Request {
+    fn new(max_body_size: u64) -> HttpParserHandler {
+        HttpParserHandler { max_body_size: max_body_size, ..HttpParserHandler::default() }
+    }
+
+    fn refuse_body(&mut self) -> Result<(), ParseError> {
+        self.too_large = true;
+        Err(ParseError::new("Payload Too Large"))
+    }
+
+    pub fn build_request(self, stream: &TcpStream) -> Request {
         let version_vec: Vec<&str> = self.version.split('.').collect();
         let http_version = (version_vec[0].parse().unwrap(), version_vec[1].parse().unwrap());
         let query = self.query.clone().map(|q| Query::from_str(&q));
@@ -30,14 +48,20 @@ impl HttpParserHandler {
             &self.url,
             query,
             http_version,
-            Headers::with_data(self.headers.clone()),
-            None,
+            Headers::with_data(self.headers),
+            self.content_length,
+            self.body,
             stream,
         )
     }
 }
 
 impl ParserHandler for HttpParserHandler {
+    fn on_message_begin(&mut self) -> Result<(), ParseError> {
+        self.started = true;
+        Ok(())
+    }
+
     fn on_method(&mut self, method: &str) -> Result<(), ParseError> {
         self.method = method.to_owned();
         Ok(())
@@ -59,11 +83,186 @@ impl ParserHandler for HttpParserHandler {
     }
 
     fn on_header(&mut self, field: &str, values: Vec<&str>) -> Result<(), ParseError> {
-        self.headers.insert(field.to_owned(), values.into_iter().map(|val| val.to_owned()).collect());
+        if field.to_lowercase() == "content-length" {
+            self.content_length = values[0].parse().ok();
+        }
+        // a field sent on several lines is the same as one listing its values
+        self.headers.entry(field.to_owned()).or_insert(Vec::new()).extend(values.into_iter().map(|val| val.to_owned()));
+        Ok(())
+    }
+
+    fn on_headers_complete(&mut self) -> Result<(), ParseError> {
+        // refused before any of it is read
+        match self.content_length {
+            Some(length) if length > self.max_body_size => self.refuse_body(),
+            _ => Ok(()),
+        }
+    }
+
+    fn on_body(&mut self, part: &[u8]) -> Result<(), ParseError> {
+        // chunked bodies only show their size as they arrive
+        if (self.body.len() + part.len()) as u64 > self.max_body_size {
+            return self.refuse_body();
+        }
+        self.body.extend(part.iter().cloned());
         Ok(())
     }
 }
 
+const DEFAULT_MAX_REQUESTS: usize = 100;
+const DEFAULT_KEEP_ALIVE_TIMEOUT: u64 = 5;
+const DEFAULT_MAX_BODY_SIZE: u64 = 1024 * 1024;
+
+/// How often an idle connection checks whether others wait for its thread
+const IDLE_POLL_INTERVAL_MS: u64 = 100;
+
+/// Connections accepted and not yet closed, against the threads serving them
+///
+/// Each connection holds a thread for as long as it is open, so when there
+/// are more connections than threads some wait in the queue of the pool
+struct Workers {
+    threads: usize,
+    connections: AtomicUsize,
+}
+
+impl Workers {
+    fn is_saturated(&self) -> bool {
+        self.connections.load(Ordering::SeqCst) > self.threads
+    }
+}
+
+/// Count a connection as closed when it is dropped, even if serving it panicked
+struct Connection<'a> {
+    workers: &'a Workers,
+}
+
+impl<'a> Drop for Connection<'a> {
+    fn drop(&mut self) {
+        self.workers.connections.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Wait for the next request to start arriving on an idle connection
+///
+/// The connection is given up when it idles for longer than `timeout`, or
+/// as soon as another connection waits for a thread
+fn wait_for_request(reader: &mut BufReader<TcpStream>, stream: &TcpStream,
+                    timeout: Option<Duration>, workers: &Workers) -> bool {
+    if let Err(e) = stream.set_read_timeout(Some(Duration::from_millis(IDLE_POLL_INTERVAL_MS))) {
+        println!("Error setting read timeout: '{}'", e);
+        return false;
+    }
+
+    let idle_since = Instant::now();
+    loop {
+        match reader.fill_buf() {
+            // data, or the end of the stream which the parser reports
+            Ok(_) => break,
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut
+                    || e.kind() == ErrorKind::Interrupted => {
+                let expired = timeout.map(|timeout| idle_since.elapsed() >= timeout).unwrap_or(false);
+                if expired || workers.is_saturated() {
+                    return false;
+                }
+            },
+            Err(_) => return false,
+        }
+    }
+
+    if let Err(e) = stream.set_read_timeout(timeout) {
+        println!("Error setting read timeout: '{}'", e);
+        return false;
+    }
+    true
+}
+
+/// Answer a request whose body is over the limit, closing the connection
+/// as the rest of the body is left unread
+fn send_payload_too_large(stream: &TcpStream, http_version: &str) -> Result<(), io::Error> {
+    let mut response = match Response::from_stream(stream) {
+        Ok(response) => response,
+        Err(e) => return Err(io::Error::new(ErrorKind::Other, e.to_string())),
+    };
+    let body = "413 - Payload Too Large";
+
+    if http_version == "1.1" {
+        response.with_http_version("1.1");
+    }
+    response.with_status(413, "Payload Too Large")
+        .with_header("Content-Type", "text/plain; charset=utf-8")
+        .with_header("Content-Length", &body.len().to_string());
+
+    response.start(|res| {
+        try!(res.write_all(body.as_bytes()));
+        try!(res.flush());
+        Ok(())
+    })
+}
+
+/// Serve requests from a single connection until either side closes it
+fn serve_connection(stream: TcpStream, handler: &Box<Handler + Send + Sync>, max_requests: usize,
+                    max_body_size: u64, timeout: Option<Duration>, workers: &Workers) {
+    let _connection = Connection { workers: workers };
+
+    let mut reader = match stream.try_clone() {
+        Ok(s) => BufReader::new(s),
+        Err(e) => {
+            println!("Error cloning stream: '{}'", e);
+            return;
+        }
+    };
+
+    let mut served = 0;
+    loop {
+        if !wait_for_request(&mut reader, &stream, timeout, workers) {
+            break;
+        }
+
+        let mut http_parser = HttpParserHandler::new(max_body_size);
+
+        if let Err(e) = Parser::request(&mut http_parser).parse(&mut reader) {
+            if http_parser.too_large {
+                if let Err(e) = send_payload_too_large(&stream, &http_parser.version) {
+                    println!("Error handling request: '{}'", e);
+                }
+                break;
+            }
+
+            // an idle connection timing out is not worth reporting
+            if served == 0 {
+                println!("Erro parsing request: '{}'", e);
+            }
+            break;
+        }
+
+        // the client closed the connection
+        if !http_parser.started {
+            break;
+        }
+
+        served += 1;
+
+        let mut request = http_parser.build_request(&stream);
+        let mut response = Response::from_stream(&stream).unwrap();
+
+        if request.http_version() >= (1, 1) {
+            response.with_http_version("1.1");
+        }
+        // a connection kept open would hold its thread from those waiting
+        response.with_keep_alive(request.keep_alive() && served < max_requests && !workers.is_saturated());
+        response.with_head_request(request.method() == "HEAD");
+
+        if let Err(e) = handler.handle_request(&mut request, &mut response) {
+            println!("Error handling request: '{}'", e);
+            break;
+        }
+
+        if !response.keep_alive() {
+            break;
+        }
+    }
+}
+
 /// Server that listen for connections on given address
 ///
 /// The server will listen for connections on the given address,
@@ -87,6 +286,10 @@ pub struct HttpServer {
     addr: String,
     listener: TcpListener,
     threadpool: ThreadPool,
+    workers: Arc<Workers>,
+    max_requests: usize,
+    max_body_size: u64,
+    keep_alive_timeout: Option<Duration>,
 }
 
 impl HttpServer {
@@ -98,9 +301,39 @@ impl HttpServer {
             addr: addr.to_string(),
             listener: listener,
             threadpool: ThreadPool::new(num_threads),
+            workers: Arc::new(Workers { threads: num_threads, connections: AtomicUsize::new(0) }),
+            max_requests: DEFAULT_MAX_REQUESTS,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            keep_alive_timeout: Some(Duration::from_secs(DEFAULT_KEEP_ALIVE_TIMEOUT)),
         }
     }
 
+    /// Set how many requests may be served over a single connection
+    ///
+    /// A value of 1 disables persistent connections
+    pub fn with_max_requests(&mut self, max_requests: usize) -> &mut Self {
+        self.max_requests = max_requests;
+        self
+    }
+
+    /// Set the largest request body accepted, in bytes
+    ///
+    /// Larger bodies are answered with 413 before being read when their
+    /// length is announced, and as soon as they pass the limit otherwise
+    pub fn with_max_body_size(&mut self, max_body_size: u64) -> &mut Self {
+        self.max_body_size = max_body_size;
+        self
+    }
+
+    /// Set how long an idle connection is kept open waiting for a request
+    ///
+    /// `None` waits indefinitely. Idle connections are closed early when
+    /// every thread is taken and other connections wait for one
+    pub fn with_keep_alive_timeout(&mut self, timeout: Option<Duration>) -> &mut Self {
+        self.keep_alive_timeout = timeout;
+        self
+    }
+
     /// Start the server with the given handler
     ///
     /// When started, the server will block and listen for connections,
@@ -110,22 +343,16 @@ impl HttpServer {
         let arc = Arc::new(handler);
         for stream in self.listener.incoming() {
             match stream {
-                Ok(mut stream) => {
+                Ok(stream) => {
                     let handler = arc.clone();
+                    let max_requests = self.max_requests;
+                    let max_body_size = self.max_body_size;
+                    let timeout = self.keep_alive_timeout;
+                    let workers = self.workers.clone();
+                    workers.connections.fetch_add(1, Ordering::SeqCst);
 
                     self.threadpool.execute(move || {
-                        let mut http_parser = HttpParserHandler::default();
-
-                        Parser::request(&mut http_parser).parse(&mut stream).unwrap_or_else(|e| {
-                            println!("Erro parsing request: '{}'", e);
-                        });
-
-                        let mut request = http_parser.build_request(&stream);
-                        let mut response = Response::from_stream(&stream).unwrap();
-
-                        handler.handle_request(&mut request, &mut response).unwrap_or_else(|e| {
-                            println!("Error handling request: '{}'", e);
-                        });
+                        serve_connection(stream, &handler, max_requests, max_body_size, timeout, &workers);
                     });
                 },
                 Err(error) => println!("{:?}", error),